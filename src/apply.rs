@@ -1,10 +1,62 @@
-use crate::dvd::convert_dvd_vobs_to_single_mp4;
+use crate::apply_log::{self, ApplyEvent, EventLog};
+use crate::atomic;
+use crate::cancel;
+use crate::deduplicate::blake3_hash_file;
+use crate::dvd::{convert_dvd_vobs_to_single_mp4, dvd_conversion_commands};
+use crate::journal::{CreatedFile, ItemEffects, ItemState, Journal, ReleasedSource};
 use crate::plan::{Action, PlannedItem};
-use crate::video::ffmpeg_convert_to_mp4;
+use crate::progress::Progress;
+use crate::video::{EncodingProfile, ffmpeg_convert_command, ffmpeg_convert_to_mp4};
+use crate::workers;
+use crate::xmp;
 use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs::{self, OpenOptions};
-use std::io::Write;
-use std::path::{Path, PathBuf};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// What apply does with an exact duplicate, whose content is already written
+/// by its canonical item.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicatePolicy {
+    /// Leave the duplicate out of the library.
+    #[default]
+    Skip,
+    /// Hard-link the duplicate's destination to the canonical output.
+    Hardlink,
+    /// Copy-on-write clone of the canonical output (btrfs, XFS).
+    Reflink,
+    /// Symlink the duplicate's destination to the canonical output.
+    Symlink,
+}
+
+/// What apply does when an item's destination already holds different
+/// content.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// Write the item next to it as `name_1.ext`, `name_2.ext`, ...
+    #[default]
+    Rename,
+    /// Replace the existing file.
+    Overwrite,
+    /// Fail the item and leave the existing file alone.
+    Fail,
+}
+
+/// How a destination conflict was resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Conflict {
+    Renamed,
+    Overwritten,
+}
 
 pub struct ApplySummary {
     pub total: u64,
@@ -13,7 +65,25 @@ pub struct ApplySummary {
     pub converted_dvd: u64,
     pub skipped_existing: u64,
     pub skipped_dupliace: u64,
+    pub linked_duplicates: u64,
+    pub skipped_in_library: u64,
+    /// Sources removed (or quarantined) after their content was verified.
+    pub moved: u64,
     pub failed: u64,
+    /// Items stopped part-way by Ctrl-C, left in progress in the journal.
+    pub cancelled: u64,
+    /// Items written under a new name because their destination held
+    /// different content.
+    pub conflicts_renamed: u64,
+    pub conflicts_overwritten: u64,
+    /// Interrupted items picked up from the journal.
+    pub resumed: u64,
+    /// Items the journal says need no work this run.
+    pub skipped_journal: u64,
+    /// Items that failed in an earlier run and were left for `--retry-failed`.
+    pub previously_failed: u64,
+    /// Temp files from interrupted runs that were cleaned up.
+    pub stale_temps_removed: usize,
 }
 
 impl ApplySummary {
@@ -25,11 +95,48 @@ impl ApplySummary {
             converted_dvd: 0,
             skipped_existing: 0,
             skipped_dupliace: 0,
+            linked_duplicates: 0,
+            skipped_in_library: 0,
+            moved: 0,
             failed: 0,
+            cancelled: 0,
+            conflicts_renamed: 0,
+            conflicts_overwritten: 0,
+            resumed: 0,
+            skipped_journal: 0,
+            previously_failed: 0,
+            stale_temps_removed: 0,
         }
     }
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct ApplyOptions {
+    /// Write an `.xmp` sidecar recording the source filename next to renamed outputs.
+    pub xmp_sidecars: bool,
+    /// Where the run's `apply-<run_id>.jsonl` event log is written.
+    pub log_dir: PathBuf,
+    pub run_id: String,
+    pub video_encoding: EncodingProfile,
+    pub dvd_encoding: EncodingProfile,
+    pub duplicates: DuplicatePolicy,
+    /// Where moved sources go instead of being deleted.
+    pub quarantine: Option<PathBuf>,
+    /// Re-read every copy and compare its hash with the source.
+    pub verify_copies: bool,
+    /// Only reprocess items the journal records as failed.
+    pub retry_failed: bool,
+    /// What to do when a destination exists with different content.
+    pub conflicts: ConflictPolicy,
+    /// Workers for copies, moves and duplicate links.
+    pub copy_jobs: usize,
+    /// Workers for video and DVD conversions.
+    pub ffmpeg_jobs: usize,
+    /// Decide every item's outcome without writing, removing or encoding
+    /// anything; events are printed instead of logged.
+    pub dry_run: bool,
+}
+
 pub fn ensure_parent_dir(dst: &Path) -> Result<()> {
     if let Some(parent) = dst.parent() {
         fs::create_dir_all(parent)?;
@@ -37,73 +144,1366 @@ pub fn ensure_parent_dir(dst: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Copies tried before a file that keeps failing verification is given up on.
+const COPY_ATTEMPTS: u32 = 3;
+
 fn copy_file(src: &Path, dst: &Path) -> Result<()> {
+    atomic::write_atomically(dst, |tmp| {
+        fs::copy(src, tmp)
+            .with_context(|| format!("copy {} -> {}", src.display(), dst.display()))?;
+        Ok(())
+    })
+}
+
+/// Copies `src` and re-reads the temp copy before it is renamed into place,
+/// retrying when its blake3 hash differs from the source. Returns the hash.
+fn copy_file_verified(src: &Path, dst: &Path) -> Result<String> {
+    let expected = blake3_hash_file(src).with_context(|| format!("hash {}", src.display()))?;
+
+    let mut attempt = 1;
+    loop {
+        let result = atomic::write_atomically(dst, |tmp| {
+            fs::copy(src, tmp)
+                .with_context(|| format!("copy {} -> {}", src.display(), dst.display()))?;
+            // Read the copy back from disk rather than from the page cache.
+            let written = fs::File::open(tmp)?;
+            written.sync_all()?;
+            drop_cached_pages(&written);
+            let actual = blake3_hash_file(tmp)?;
+            anyhow::ensure!(
+                actual == expected,
+                "copy of {} does not match the source (attempt {attempt}/{COPY_ATTEMPTS})",
+                src.display()
+            );
+            Ok(())
+        });
+        match result {
+            Ok(()) => return Ok(expected.to_hex().to_string()),
+            Err(e) if attempt >= COPY_ATTEMPTS => return Err(e),
+            Err(_) => attempt += 1,
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn drop_cached_pages(file: &fs::File) {
+    use std::os::fd::AsRawFd;
+
+    // Advisory only: if the kernel keeps the pages, the hash still runs.
+    // SAFETY: the descriptor is open for the duration of the call.
+    unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) };
+}
+
+#[cfg(not(target_os = "linux"))]
+fn drop_cached_pages(_file: &fs::File) {}
+
+#[cfg(target_os = "linux")]
+fn reflink_file(src: &Path, dst: &Path) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let from = fs::File::open(src)?;
+    let to = OpenOptions::new().write(true).create_new(true).open(dst)?;
+    // SAFETY: both descriptors are open for the duration of the call.
+    let rc = unsafe { libc::ioctl(to.as_raw_fd(), libc::FICLONE, from.as_raw_fd()) };
+    if rc != 0 {
+        let err = io::Error::last_os_error();
+        drop(to);
+        let _ = fs::remove_file(dst);
+        return Err(err);
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink_file(_src: &Path, _dst: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "reflinks are only supported on Linux",
+    ))
+}
+
+#[cfg(unix)]
+fn symlink_file(target: &Path, dst: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, dst)
+}
+
+#[cfg(not(unix))]
+fn symlink_file(target: &Path, dst: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(target, dst)
+}
+
+/// Places a duplicate at its own destination by linking to the canonical
+/// output `target` instead of writing its bytes again.
+fn link_duplicate(policy: DuplicatePolicy, target: &Path, dst: &Path) -> Result<()> {
+    anyhow::ensure!(
+        target.exists(),
+        "canonical output {} does not exist",
+        target.display()
+    );
     ensure_parent_dir(dst)?;
-    fs::copy(src, dst).with_context(|| format!("copy {} -> {}", src.display(), dst.display()))?;
+
+    match policy {
+        DuplicatePolicy::Skip => return Ok(()),
+        DuplicatePolicy::Hardlink => fs::hard_link(target, dst),
+        DuplicatePolicy::Reflink => reflink_file(target, dst),
+        DuplicatePolicy::Symlink => symlink_file(&target.canonicalize()?, dst),
+    }
+    .with_context(|| format!("{policy:?} {} -> {}", dst.display(), target.display()))
+}
+
+/// `src` re-rooted under `dir`, keeping its directories so quarantined
+/// files from different folders cannot collide.
+fn quarantine_path(dir: &Path, src: &Path) -> PathBuf {
+    let rel: PathBuf = src
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .collect();
+    dir.join(rel)
+}
+
+/// Removes `src` once `copy` is verified to hold the same bytes, or moves it
+/// under `quarantine` if one is given. Anything that fails to verify stays.
+fn release_source(
+    src: &Path,
+    copy: &Path,
+    quarantine: Option<&Path>,
+    checkpoint: Checkpoint,
+) -> Result<ReleasedSource> {
+    anyhow::ensure!(
+        src.canonicalize()? != copy.canonicalize()?,
+        "{} is its own destination; source kept",
+        src.display()
+    );
+    let src_hash = blake3_hash_file(src).with_context(|| format!("hash {}", src.display()))?;
+    let copy_hash = blake3_hash_file(copy).with_context(|| format!("hash {}", copy.display()))?;
+    anyhow::ensure!(
+        src_hash == copy_hash,
+        "{} does not match {}; source kept",
+        copy.display(),
+        src.display()
+    );
+
+    let target = quarantine.map(|dir| quarantine_path(dir, src));
+    let released = ReleasedSource {
+        verified_against: copy.to_string_lossy().to_string(),
+        hash: src_hash.to_hex().to_string(),
+        quarantined_to: target.as_ref().map(|t| t.to_string_lossy().to_string()),
+    };
+    // Journaled first: if the run dies mid-removal, undo can still find
+    // the source's content, and restores nothing that is still in place.
+    checkpoint(&ItemEffects {
+        released: Some(released.clone()),
+        ..Default::default()
+    })?;
+    match &target {
+        None => fs::remove_file(src).with_context(|| format!("remove {}", src.display()))?,
+        Some(target) => move_file(src, target)
+            .with_context(|| format!("quarantine {} -> {}", src.display(), target.display()))?,
+    }
+    Ok(released)
+}
+
+/// Renames, falling back to copy and delete across filesystems.
+pub fn move_file(from: &Path, to: &Path) -> Result<()> {
+    ensure_parent_dir(to)?;
+    if fs::rename(from, to).is_err() {
+        fs::copy(from, to)?;
+        fs::remove_file(from)?;
+    }
     Ok(())
 }
-pub fn apply_items(items: &[PlannedItem]) -> Result<ApplySummary> {
-    let mut ok_log = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open("apply_ok.log")?;
-    let mut fail_log = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open("apply_fail.log")?;
-    let mut dup_log = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open("apply_duplicates_skipped.log")?;
 
-    let mut summary = ApplySummary::new();
+/// Returns the sidecar's path when one was written.
+fn write_sidecar_if_renamed(item: &PlannedItem, dst: &Path) -> Result<Option<PathBuf>> {
+    let Some(original) = &item.original_name else {
+        return Ok(None);
+    };
+    let renamed = dst.file_stem().map(|s| s.to_string_lossy())
+        != Path::new(original).file_stem().map(|s| s.to_string_lossy());
+    let sidecar = xmp::sidecar_path(dst);
+    if !renamed || sidecar.exists() {
+        return Ok(None);
+    }
+    xmp::write_preserved_filename_sidecar(dst, original)?;
+    Ok(Some(sidecar))
+}
 
-    for item in items {
-        summary.total += 1;
+fn file_name_lossy(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
 
-        if let Some(canon) = &item.duplicate_of {
-            summary.skipped_dupliace += 1;
-            writeln!(dup_log, "SKIP_DUP\t{}\tdup_of={}", item.src, canon)?;
-            continue;
+/// Ancestors of `path` that do not exist yet, deepest first.
+fn missing_dirs(path: &Path) -> Vec<String> {
+    path.ancestors()
+        .skip(1)
+        .take_while(|d| !d.as_os_str().is_empty() && !d.exists())
+        .map(|d| d.to_string_lossy().to_string())
+        .collect()
+}
+
+/// Records a file this run wrote, hashing it unless the hash is known.
+fn created_file(path: &Path, hash: Option<String>) -> Result<CreatedFile> {
+    let hash = match hash {
+        Some(h) => h,
+        None => blake3_hash_file(path)
+            .with_context(|| format!("hash {}", path.display()))?
+            .to_hex()
+            .to_string(),
+    };
+    Ok(CreatedFile {
+        path: path.to_string_lossy().to_string(),
+        hash,
+        replaced: false,
+    })
+}
+
+/// What one item ended up as, for the summary and the event log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Copied,
+    ConvertedVideo,
+    ConvertedDvd,
+    SkippedExisting,
+    SkippedDuplicate,
+    LinkedDuplicate,
+    SkippedInLibrary,
+    Failed,
+    /// Stopped part-way by Ctrl-C; the next run resumes it.
+    Cancelled,
+}
+
+struct Applied {
+    outcome: Outcome,
+    effects: ItemEffects,
+    /// Content the item was matched to instead of being written.
+    target: Option<String>,
+    conflict: Option<Conflict>,
+    /// Where the item went, when not its planned destination.
+    written_to: Option<String>,
+    warnings: Vec<String>,
+    /// ffmpeg commands a dry run would have run.
+    commands: Vec<String>,
+}
+
+impl Applied {
+    fn new(outcome: Outcome) -> Self {
+        Self {
+            outcome,
+            effects: ItemEffects::default(),
+            target: None,
+            conflict: None,
+            written_to: None,
+            warnings: Vec::new(),
+            commands: Vec::new(),
         }
+    }
 
-        let src = PathBuf::from(&item.src);
-        let dst = PathBuf::from(&item.dst);
+    fn set_conflict(&mut self, conflict: Option<Conflict>, dst: &Path) {
+        self.conflict = conflict;
+        if conflict == Some(Conflict::Renamed) {
+            self.written_to = Some(dst.to_string_lossy().to_string());
+        }
+    }
+}
 
-        if dst.exists() {
-            summary.skipped_existing += 1;
-            continue;
+/// `release_source` for `Move` items; other actions keep their sources. A
+/// resumed item whose source is already gone was released before the
+/// interruption.
+fn release(
+    item: &PlannedItem,
+    copy: &Path,
+    options: &ApplyOptions,
+    resuming: bool,
+    checkpoint: Checkpoint,
+) -> Result<Option<ReleasedSource>> {
+    let src = Path::new(&item.src);
+    if !matches!(item.action, Action::Move) || (resuming && !src.exists()) {
+        return Ok(None);
+    }
+    if options.dry_run {
+        return Ok(Some(ReleasedSource {
+            verified_against: copy.to_string_lossy().to_string(),
+            hash: item.content_hash.clone().unwrap_or_default(),
+            quarantined_to: options
+                .quarantine
+                .as_deref()
+                .map(|dir| quarantine_path(dir, src).to_string_lossy().to_string()),
+        }));
+    }
+    release_source(src, copy, options.quarantine.as_deref(), checkpoint).map(Some)
+}
+
+/// Whether `path` already holds `source`'s content, comparing sizes before
+/// hashes. `source_hash` saves re-reading a source the plan already hashed.
+fn same_content(path: &Path, source: &Path, source_hash: Option<&str>) -> Result<bool> {
+    let len = |p: &Path| fs::metadata(p).map(|m| m.len());
+    if len(path).with_context(|| format!("stat {}", path.display()))?
+        != len(source).with_context(|| format!("stat {}", source.display()))?
+    {
+        return Ok(false);
+    }
+    let hash = |p: &Path| -> Result<String> {
+        Ok(blake3_hash_file(p)
+            .with_context(|| format!("hash {}", p.display()))?
+            .to_hex()
+            .to_string())
+    };
+    let expected = match source_hash {
+        Some(h) => h.to_string(),
+        None => hash(source)?,
+    };
+    Ok(hash(path)? == expected)
+}
+
+/// The journal's record of `path`, if an earlier attempt at the item wrote
+/// it and it has not changed since.
+fn written_earlier<'a>(earlier: &'a ItemEffects, path: &Path) -> Result<Option<&'a CreatedFile>> {
+    let Some(file) = earlier.created.iter().find(|f| Path::new(&f.path) == path) else {
+        return Ok(None);
+    };
+    let hash = blake3_hash_file(path).with_context(|| format!("hash {}", path.display()))?;
+    Ok((hash.to_hex().to_string() == file.hash).then_some(file))
+}
+
+fn wrote_earlier(earlier: &ItemEffects, path: &Path) -> Result<bool> {
+    Ok(written_earlier(earlier, path)?.is_some())
+}
+
+/// `photo.jpg` -> `photo_1.jpg`, `photo_2.jpg`, ...
+fn numbered_path(dst: &Path, n: u32) -> PathBuf {
+    let stem = dst
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let name = match dst.extension() {
+        Some(ext) => format!("{stem}_{n}.{}", ext.to_string_lossy()),
+        None => format!("{stem}_{n}"),
+    };
+    dst.with_file_name(name)
+}
+
+/// Where an item whose destination is already taken ends up.
+enum Placement {
+    /// The content is already at this path; nothing to write.
+    Present(PathBuf),
+    Write(PathBuf, Option<Conflict>),
+}
+
+/// Destinations taken by items of this run, with the content hash each
+/// will hold when known. Two items planned to the same path would otherwise
+/// both find it free and write over each other.
+struct Claims {
+    paths: Mutex<HashMap<PathBuf, Option<String>>>,
+    /// In a dry run nothing is written, so claimed paths stand in for the
+    /// files earlier items would have created.
+    simulated: bool,
+}
+
+impl Claims {
+    fn new(simulated: bool) -> Self {
+        Self {
+            paths: Mutex::default(),
+            simulated,
         }
+    }
 
-        let result = match item.action {
-            Action::Copy => copy_file(&src, &dst),
-            Action::ConvertVideo => ffmpeg_convert_to_mp4(&src, &dst),
-            Action::ConvertDvd => convert_dvd_vobs_to_single_mp4(&src, &dst),
-        };
+    /// Reserves `path` for the calling item; false if another item has it.
+    fn reserve(&self, path: &Path, content: Option<&str>) -> bool {
+        let mut paths = self.paths.lock().unwrap();
+        if paths.contains_key(path) {
+            return false;
+        }
+        paths.insert(path.to_path_buf(), content.map(str::to_string));
+        true
+    }
 
-        match result {
-            Ok(()) => {
-                match item.action {
-                    Action::Copy => summary.copied += 1,
-                    Action::ConvertVideo => summary.converted_video += 1,
-                    Action::ConvertDvd => summary.converted_dvd += 1,
+    fn contains(&self, path: &Path) -> bool {
+        self.paths.lock().unwrap().contains_key(path)
+    }
+
+    /// Whether `path` holds a file, or would by now in a dry run.
+    fn exists(&self, path: &Path) -> bool {
+        path.exists() || path.is_symlink() || (self.simulated && self.contains(path))
+    }
+
+    /// For a path only a dry run's earlier item would have written: whether
+    /// it would hold `content`.
+    fn simulated_same(&self, path: &Path, content: Option<&str>) -> Option<bool> {
+        if !self.simulated || path.exists() || path.is_symlink() {
+            return None;
+        }
+        let paths = self.paths.lock().unwrap();
+        let claimed = paths.get(path)?;
+        Some(content.is_some() && claimed.as_deref() == content)
+    }
+}
+
+/// Picks where an item planned for `dst` goes and reserves it in `claims`.
+/// `is_same` tells whether an existing path already holds the item's
+/// content, whose hash is `content` when known. Outputs of other items in
+/// the same run are never replaced, even under `Overwrite`: the item is
+/// renamed instead.
+fn place(
+    dst: &Path,
+    policy: ConflictPolicy,
+    claims: &Claims,
+    content: Option<&str>,
+    is_same: impl Fn(&Path) -> Result<bool>,
+) -> Result<Placement> {
+    let same = |p: &Path| match claims.simulated_same(p, content) {
+        Some(same) => Ok(same),
+        None => is_same(p),
+    };
+    if !claims.exists(dst) && claims.reserve(dst, content) {
+        return Ok(Placement::Write(dst.to_path_buf(), None));
+    }
+    if claims.exists(dst) && same(dst)? {
+        return Ok(Placement::Present(dst.to_path_buf()));
+    }
+    match policy {
+        ConflictPolicy::Overwrite if claims.reserve(dst, content) => Ok(Placement::Write(
+            dst.to_path_buf(),
+            Some(Conflict::Overwritten),
+        )),
+        ConflictPolicy::Fail if claims.contains(dst) => {
+            anyhow::bail!("{} is also the destination of another item", dst.display())
+        }
+        ConflictPolicy::Fail => anyhow::bail!("{} exists with different content", dst.display()),
+        ConflictPolicy::Rename | ConflictPolicy::Overwrite => {
+            // A rerun finds the copy an earlier run renamed.
+            for n in 1.. {
+                let candidate = numbered_path(dst, n);
+                if !claims.exists(&candidate) && claims.reserve(&candidate, content) {
+                    return Ok(Placement::Write(candidate, Some(Conflict::Renamed)));
+                }
+                if claims.exists(&candidate) && same(&candidate)? {
+                    return Ok(Placement::Present(candidate));
                 }
-                writeln!(
-                    ok_log,
-                    "OK\t{:?}\t{}\t->\t{}",
-                    item.action, item.src, item.dst
-                )?;
             }
-            Err(e) => {
-                summary.failed += 1;
-                writeln!(
-                    fail_log,
-                    "FAIL\t{:?}\t{}\t->\t{}\t[{}]",
-                    item.action, item.src, item.dst, e
-                )?;
+            unreachable!("ran out of numbered names for {}", dst.display())
+        }
+    }
+}
+
+/// Records what an item has written so far in the journal, before the
+/// steps that can still fail.
+type Checkpoint<'a> = &'a dyn Fn(&ItemEffects) -> Result<()>;
+
+fn apply_duplicate(
+    item: &PlannedItem,
+    target: &Path,
+    options: &ApplyOptions,
+    resuming: bool,
+    claims: &Claims,
+    checkpoint: Checkpoint,
+) -> Result<Applied> {
+    let mut applied = if options.duplicates == DuplicatePolicy::Skip {
+        Applied::new(Outcome::SkippedDuplicate)
+    } else {
+        // In a dry run the canonical output may not have been written yet.
+        let placement = place(
+            Path::new(&item.dst),
+            options.conflicts,
+            claims,
+            item.content_hash.as_deref(),
+            |p| Ok(target.exists() && same_content(p, target, None)?),
+        )?;
+        match placement {
+            Placement::Present(_) => Applied::new(Outcome::SkippedExisting),
+            Placement::Write(dst, conflict) if options.dry_run => {
+                let mut applied = Applied::new(Outcome::LinkedDuplicate);
+                applied.set_conflict(conflict, &dst);
+                applied
+            }
+            Placement::Write(dst, conflict) => {
+                if conflict == Some(Conflict::Overwritten) {
+                    fs::remove_file(&dst).with_context(|| format!("remove {}", dst.display()))?;
+                }
+                let new_dirs = missing_dirs(&dst);
+                link_duplicate(options.duplicates, target, &dst)?;
+                let mut applied = Applied::new(Outcome::LinkedDuplicate);
+                let mut link = created_file(&dst, None)?;
+                link.replaced = conflict == Some(Conflict::Overwritten);
+                applied.effects.created_dirs = new_dirs;
+                applied.effects.created.push(link);
+                applied.set_conflict(conflict, &dst);
+                checkpoint(&applied.effects)?;
+                applied
+            }
+        }
+    };
+    applied.target = Some(target.to_string_lossy().to_string());
+
+    // Verified against the canonical output, which a symlink points at.
+    applied.effects.released = release(item, target, options, resuming, checkpoint)?;
+    Ok(applied)
+}
+
+/// For an item whose content is already at `dst`.
+fn finish_existing(
+    item: &PlannedItem,
+    dst: &Path,
+    options: &ApplyOptions,
+    resuming: bool,
+    earlier: &ItemEffects,
+    checkpoint: Checkpoint,
+) -> Result<Applied> {
+    let mut applied = Applied::new(Outcome::SkippedExisting);
+    if dst != Path::new(&item.dst) {
+        applied.written_to = Some(dst.to_string_lossy().to_string());
+    }
+    // Only what an earlier attempt journaled as its own: a file that was
+    // already there, or changed since, is not this item's to undo.
+    if !options.dry_run {
+        for path in [dst.to_path_buf(), xmp::sidecar_path(dst)] {
+            if path.exists()
+                && let Some(file) = written_earlier(earlier, &path)?
+            {
+                applied.effects.created.push(file.clone());
+            }
+        }
+    }
+    if options.xmp_sidecars && !options.dry_run {
+        match write_sidecar_if_renamed(item, dst) {
+            Ok(Some(sidecar)) => applied.effects.created.push(created_file(&sidecar, None)?),
+            Ok(None) => {}
+            Err(e) => applied.warnings.push(format!("xmp sidecar: {e:#}")),
+        }
+    }
+    // A previous run may have copied the file but stopped before
+    // removing the source.
+    applied.effects.released = release(item, dst, options, resuming, checkpoint)?;
+    Ok(applied)
+}
+
+fn apply_one(
+    item: &PlannedItem,
+    options: &ApplyOptions,
+    resuming: bool,
+    earlier: &ItemEffects,
+    progress: &Progress,
+    claims: &Claims,
+    checkpoint: Checkpoint,
+) -> Result<Applied> {
+    if let Some(existing) = &item.already_in_library {
+        let mut applied = Applied::new(Outcome::SkippedInLibrary);
+        applied.target = Some(existing.clone());
+        applied.effects.released =
+            release(item, Path::new(existing), options, resuming, checkpoint)?;
+        return Ok(applied);
+    }
+
+    let src = PathBuf::from(&item.src);
+    let planned_dst = PathBuf::from(&item.dst);
+    let converts = matches!(item.action, Action::ConvertVideo | Action::ConvertDvd);
+
+    // Conversions cannot be compared with their source, and a resumed move
+    // may have released its source already, so an existing output is then
+    // only theirs if the journal says an earlier attempt wrote it; outputs
+    // only appear once complete.
+    let from_journal =
+        converts || (resuming && matches!(item.action, Action::Move) && !src.exists());
+    // An earlier attempt renamed around a conflict is looked for there first.
+    let start = earlier
+        .written_to
+        .as_deref()
+        .map(PathBuf::from)
+        .filter(|p| p.exists())
+        .unwrap_or_else(|| planned_dst.clone());
+    // A conversion's output hash is not known ahead of time.
+    let content = item.content_hash.as_deref().filter(|_| !converts);
+    let placement = place(&start, options.conflicts, claims, content, |p| {
+        if from_journal {
+            return wrote_earlier(earlier, p);
+        }
+        same_content(p, &src, item.content_hash.as_deref())
+    })?;
+    let (dst, conflict) = match placement {
+        Placement::Write(dst, conflict) => (dst, conflict),
+        Placement::Present(dst) => {
+            return finish_existing(item, &dst, options, resuming, earlier, checkpoint);
+        }
+    };
+    if options.dry_run {
+        let mut applied = simulate_write(item, &src, &dst, options)?;
+        applied.effects.released = release(item, &dst, options, resuming, checkpoint)?;
+        applied.set_conflict(conflict, &dst);
+        return Ok(applied);
+    }
+
+    let new_dirs = missing_dirs(&dst);
+    let mut hash = None;
+    let outcome = match item.action {
+        Action::Copy | Action::Move => {
+            if options.verify_copies {
+                hash = Some(copy_file_verified(&src, &dst)?);
+            } else {
+                copy_file(&src, &dst)?;
+            }
+            Outcome::Copied
+        }
+        Action::ConvertVideo => {
+            let name = file_name_lossy(&dst);
+            let converted = ffmpeg_convert_to_mp4(&src, &dst, &options.video_encoding, &|secs| {
+                let done = match item.duration_secs {
+                    Some(total) if total > 0.0 => format!("{:.0}%", 100.0 * secs / total),
+                    _ => format!("{secs:.0}s"),
+                };
+                progress.set_active(&item.dst, Some(format!("{name} {done}")));
+            });
+            progress.set_active(&item.dst, None);
+            converted?;
+            Outcome::ConvertedVideo
+        }
+        Action::ConvertDvd => {
+            let name = file_name_lossy(&dst);
+            let converted = convert_dvd_vobs_to_single_mp4(
+                &src,
+                &dst,
+                &options.dvd_encoding,
+                &|done, total| {
+                    progress.set_active(&item.dst, Some(format!("{name} VOB {done}/{total}")));
+                },
+            );
+            progress.set_active(&item.dst, None);
+            converted?;
+            Outcome::ConvertedDvd
+        }
+    };
+    let mut output = created_file(&dst, hash.clone())?;
+    output.replaced = conflict == Some(Conflict::Overwritten);
+    let mut effects = ItemEffects {
+        hash: hash.clone(),
+        created: vec![output],
+        created_dirs: new_dirs,
+        released: None,
+        written_to: (dst != planned_dst).then(|| dst.to_string_lossy().to_string()),
+    };
+    checkpoint(&effects)?;
+    if options.xmp_sidecars
+        && let Some(sidecar) = write_sidecar_if_renamed(item, &dst)?
+    {
+        effects.created.push(created_file(&sidecar, None)?);
+    }
+    effects.released = release(item, &dst, options, resuming, checkpoint)?;
+
+    let mut applied = Applied::new(outcome);
+    applied.effects = effects;
+    applied.set_conflict(conflict, &dst);
+    Ok(applied)
+}
+
+/// `program arg ...`, quoting arguments the shell would split.
+fn command_line(cmd: &Command) -> String {
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|arg| {
+            let arg = arg.to_string_lossy();
+            if !arg.is_empty()
+                && arg
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_+=:,./@%".contains(c))
+            {
+                arg.to_string()
+            } else {
+                format!("'{}'", arg.replace('\'', r"'\''"))
             }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The dry-run stand-in for writing `dst`: the outcome the write would
+/// have, and the ffmpeg commands behind it.
+fn simulate_write(
+    item: &PlannedItem,
+    src: &Path,
+    dst: &Path,
+    options: &ApplyOptions,
+) -> Result<Applied> {
+    anyhow::ensure!(src.exists(), "source {} does not exist", src.display());
+    let tmp = atomic::temp_path(dst);
+    let (outcome, commands) = match item.action {
+        Action::Copy | Action::Move => (Outcome::Copied, Vec::new()),
+        Action::ConvertVideo => (
+            Outcome::ConvertedVideo,
+            vec![ffmpeg_convert_command(src, &tmp, &options.video_encoding)],
+        ),
+        Action::ConvertDvd => (
+            Outcome::ConvertedDvd,
+            dvd_conversion_commands(src, dst, &options.dvd_encoding)?,
+        ),
+    };
+    let mut applied = Applied::new(outcome);
+    applied.commands = commands.iter().map(command_line).collect();
+    Ok(applied)
+}
+
+/// Applies a manifest, consulting `journal` so that finished items are not
+/// redone and interrupted ones are picked up where they stopped. Copies and
+/// conversions run on separate workers; events are still logged in manifest
+/// order.
+pub fn apply_items(
+    items: &[PlannedItem],
+    options: &ApplyOptions,
+    journal: &mut Journal,
+    progress: &Progress,
+) -> Result<ApplySummary> {
+    let mut summary = ApplySummary::new();
+    if !options.dry_run {
+        summary.stale_temps_removed =
+            atomic::clean_stale_temps(items.iter().map(|i| Path::new(&i.dst)));
+    }
+
+    // Where each item's content lives once applied, for linking duplicates.
+    let outputs: HashMap<&str, &str> = items
+        .iter()
+        .map(|i| {
+            let out = i.already_in_library.as_deref().unwrap_or(&i.dst);
+            (i.src.as_str(), out)
+        })
+        .collect();
+
+    // Conversions get their own workers so they never starve the copies.
+    // Duplicates go last, once every canonical output has been written.
+    let (mut conversions, mut copies, mut links) = (Vec::new(), Vec::new(), Vec::new());
+    for (index, item) in items.iter().enumerate() {
+        let converts = matches!(item.action, Action::ConvertVideo | Action::ConvertDvd);
+        if item.duplicate_of.is_some() {
+            links.push(index);
+        } else if converts && item.already_in_library.is_none() {
+            conversions.push(index);
+        } else {
+            copies.push(index);
         }
     }
 
+    let todo: Vec<&PlannedItem> = items
+        .iter()
+        .filter(|i| is_selected(journal.state(i), options.retry_failed))
+        .collect();
+    progress.stage(
+        "Applying",
+        Some(todo.len() as u64),
+        Some(todo.iter().map(|i| work_bytes(i)).sum()),
+    );
+
+    let events = if options.dry_run {
+        EventLog::stdout()
+    } else {
+        EventLog::create(&apply_log::log_path(&options.log_dir, &options.run_id))?
+    };
+    // Canonicals finished by an earlier run may not be applied again, so
+    // their duplicates learn where they went from the journal.
+    let placed = items
+        .iter()
+        .filter(|i| i.duplicate_of.is_none())
+        .filter_map(|i| Some((i.src.clone(), journal.written_to(i)?.to_string())))
+        .collect();
+    let state = Mutex::new(ApplyState {
+        summary: &mut summary,
+        journal,
+        events,
+        placed,
+    });
+    let claims = Claims::new(options.dry_run);
+    let run = |index: usize| {
+        let shared = Shared {
+            outputs: &outputs,
+            options,
+            state: &state,
+            claims: &claims,
+            progress,
+        };
+        apply_at(index, &items[index], &shared)
+    };
+
+    let result = std::thread::scope(|s| {
+        let converting =
+            s.spawn(|| workers::for_each_queued(&conversions, options.ffmpeg_jobs, run));
+        let copied = workers::for_each_queued(&copies, options.copy_jobs, run);
+        let converted = converting.join().expect("conversion workers panicked");
+        copied.and(converted)
+    })
+    .and_then(|()| workers::for_each_queued(&links, options.copy_jobs, run));
+
+    progress.finish();
+    state.into_inner().unwrap().events.flush_pending()?;
+    result?;
     Ok(summary)
 }
+
+/// Whether a run applies an item in journal `state`: pending and interrupted
+/// items, or with `retry_failed` only the failed ones.
+pub fn is_selected(state: ItemState, retry_failed: bool) -> bool {
+    if retry_failed {
+        state == ItemState::Failed
+    } else {
+        matches!(state, ItemState::Pending | ItemState::InProgress)
+    }
+}
+
+/// Bytes an item reads and writes, for progress: nothing for duplicates and
+/// items already in the library.
+fn work_bytes(item: &PlannedItem) -> u64 {
+    if item.duplicate_of.is_some() || item.already_in_library.is_some() {
+        return 0;
+    }
+    item.size_bytes.unwrap_or(0)
+}
+
+/// What the apply workers share, behind one lock.
+struct ApplyState<'a> {
+    summary: &'a mut ApplySummary,
+    journal: &'a mut Journal,
+    events: EventLog,
+    /// Canonical outputs renamed around a conflict, by source, so their
+    /// duplicates link to where the content really went.
+    placed: HashMap<String, String>,
+}
+
+/// What every item of a run is applied with.
+struct Shared<'a, 'b> {
+    /// Where each item's content lives once applied, by source.
+    outputs: &'a HashMap<&'a str, &'a str>,
+    options: &'a ApplyOptions,
+    state: &'a Mutex<ApplyState<'b>>,
+    claims: &'a Claims,
+    progress: &'a Progress,
+}
+
+/// Applies the item at `index` in the manifest, if the journal says it
+/// still needs work, and records the result.
+fn apply_at(index: usize, item: &PlannedItem, shared: &Shared) -> Result<()> {
+    let Shared {
+        outputs,
+        options,
+        state,
+        claims,
+        progress,
+    } = *shared;
+    let (resuming, earlier) = {
+        let mut st = state.lock().unwrap();
+        // Once cancelled, nothing new is started.
+        if cancel::requested() {
+            st.events.push(index, None)?;
+            return Ok(());
+        }
+        st.summary.total += 1;
+
+        let journaled = st.journal.state(item);
+        if !is_selected(journaled, options.retry_failed) {
+            if journaled == ItemState::Failed {
+                st.summary.previously_failed += 1;
+            } else {
+                st.summary.skipped_journal += 1;
+            }
+            st.events.push(index, None)?;
+            return Ok(());
+        }
+        let resuming = journaled == ItemState::InProgress;
+        if resuming {
+            st.summary.resumed += 1;
+        }
+        let earlier = st.journal.effects(item);
+        if !options.dry_run {
+            st.journal.start(item)?;
+        }
+        (resuming, earlier)
+    };
+
+    let checkpoint = |effects: &ItemEffects| -> Result<()> {
+        if options.dry_run {
+            return Ok(());
+        }
+        state.lock().unwrap().journal.checkpoint(item, effects)
+    };
+    let started = Instant::now();
+    let result = match &item.duplicate_of {
+        Some(canon) => {
+            let placed = state.lock().unwrap().placed.get(canon).cloned();
+            let target = placed
+                .or_else(|| outputs.get(canon.as_str()).map(|o| o.to_string()))
+                .map(PathBuf::from)
+                .unwrap_or_default();
+            apply_duplicate(item, &target, options, resuming, claims, &checkpoint)
+        }
+        None => apply_one(
+            item,
+            options,
+            resuming,
+            &earlier,
+            progress,
+            claims,
+            &checkpoint,
+        ),
+    };
+    progress.advance(1, work_bytes(item));
+
+    let mut event = ApplyEvent {
+        run_id: options.run_id.clone(),
+        index,
+        src: item.src.clone(),
+        dst: item.dst.clone(),
+        action: item.action,
+        duplicate_policy: item.duplicate_of.as_ref().map(|_| options.duplicates),
+        outcome: Outcome::Failed,
+        target: None,
+        conflict: None,
+        written_to: None,
+        hash: None,
+        bytes: None,
+        duration_ms: started.elapsed().as_millis() as u64,
+        released: None,
+        error: Vec::new(),
+        warnings: Vec::new(),
+        commands: Vec::new(),
+        finished: chrono::Local::now().to_rfc3339(),
+    };
+
+    let mut st = state.lock().unwrap();
+    let summary = &mut *st.summary;
+    match result {
+        Ok(mut applied) => {
+            match applied.outcome {
+                Outcome::Copied => summary.copied += 1,
+                Outcome::ConvertedVideo => summary.converted_video += 1,
+                Outcome::ConvertedDvd => summary.converted_dvd += 1,
+                Outcome::SkippedExisting => summary.skipped_existing += 1,
+                Outcome::SkippedDuplicate => summary.skipped_dupliace += 1,
+                Outcome::LinkedDuplicate => summary.linked_duplicates += 1,
+                Outcome::SkippedInLibrary => summary.skipped_in_library += 1,
+                Outcome::Failed => summary.failed += 1,
+                Outcome::Cancelled => summary.cancelled += 1,
+            }
+            if applied.effects.released.is_some() {
+                summary.moved += 1;
+            }
+            match applied.conflict {
+                Some(Conflict::Renamed) => summary.conflicts_renamed += 1,
+                Some(Conflict::Overwritten) => summary.conflicts_overwritten += 1,
+                None => {}
+            }
+            if matches!(
+                applied.outcome,
+                Outcome::Copied | Outcome::ConvertedVideo | Outcome::ConvertedDvd
+            ) {
+                let written = applied.written_to.as_deref().unwrap_or(&item.dst);
+                event.bytes = fs::metadata(written).ok().map(|m| m.len());
+            }
+            event.outcome = applied.outcome;
+            event.target = applied.target;
+            event.conflict = applied.conflict;
+            event.written_to = applied.written_to.clone();
+            if let Some(written) = applied.written_to {
+                st.placed.insert(item.src.clone(), written);
+            }
+            event.hash = applied.effects.hash.clone();
+            event.released = applied.effects.released.clone();
+            event.warnings = applied.warnings;
+            event.commands = applied.commands;
+            applied.effects.written_to = event.written_to.clone();
+            if !options.dry_run {
+                st.journal.finish(item, Ok(applied.effects))?;
+            }
+        }
+        // Left in progress, so the journal treats it as interrupted.
+        Err(e) if cancel::requested() => {
+            summary.cancelled += 1;
+            event.outcome = Outcome::Cancelled;
+            event.error = e.chain().map(|c| c.to_string()).collect();
+        }
+        Err(e) => {
+            summary.failed += 1;
+            event.error = e.chain().map(|c| c.to_string()).collect();
+            if !options.dry_run {
+                st.journal.finish(item, Err(format!("{e:#}")))?;
+            }
+        }
+    }
+    st.events.push(index, Some(event))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::MediaKind;
+    use crate::time::DateSource;
+
+    fn photo(src: &Path, dst: &Path) -> PlannedItem {
+        PlannedItem {
+            kind: MediaKind::Photo,
+            action: Action::Copy,
+            src: src.to_string_lossy().to_string(),
+            dst: dst.to_string_lossy().to_string(),
+            best_dt: None,
+            date_source: DateSource::None,
+            size_bytes: fs::metadata(src).ok().map(|m| m.len()),
+            content_hash: None,
+            duplicate_of: None,
+            original_name: None,
+            source_root: None,
+            near_duplicate_of: None,
+            similarity: None,
+            width: None,
+            height: None,
+            duration_secs: None,
+            reencoded: None,
+            canonical_reason: None,
+            already_in_library: None,
+        }
+    }
+
+    /// An empty directory of its own for one test.
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mo-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write(path: &Path, content: &str) -> PathBuf {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+        path.to_path_buf()
+    }
+
+    /// A scratch directory holding `a/two.jpg` and `b/two.jpg`, with
+    /// different content, both planned to `out/two.jpg`.
+    fn same_destination(name: &str) -> (PathBuf, Vec<PlannedItem>) {
+        let dir = scratch(name);
+        let dst = dir.join("out/two.jpg");
+        let items = [("a", "alpha"), ("b", "beta, longer")]
+            .iter()
+            .map(|(sub, content)| photo(&write(&dir.join(sub).join("two.jpg"), content), &dst))
+            .collect();
+        (dir, items)
+    }
+
+    fn run(items: &[PlannedItem], options: &ApplyOptions, journal: &Path) -> ApplySummary {
+        let mut journal = Journal::open(journal).unwrap();
+        apply_items(items, options, &mut journal, &Progress::new(false)).unwrap()
+    }
+
+    fn options(dir: &Path) -> ApplyOptions {
+        ApplyOptions {
+            log_dir: dir.join("logs"),
+            run_id: "test".into(),
+            verify_copies: true,
+            copy_jobs: 4,
+            ffmpeg_jobs: 1,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn resume_finishes_an_item_despite_a_torn_journal_line() {
+        let dir = scratch("torn-line");
+        let item = photo(
+            &write(&dir.join("in/one.jpg"), "one"),
+            &dir.join("out/one.jpg"),
+        );
+        let journal_path = dir.join("journal.jsonl");
+        {
+            // Interrupted after copying, mid-way through the next line.
+            let mut journal = Journal::open(&journal_path).unwrap();
+            journal.start(&item).unwrap();
+            fs::copy(&item.src, write(Path::new(&item.dst), "")).unwrap();
+            let effects = ItemEffects {
+                created: vec![created_file(Path::new(&item.dst), None).unwrap()],
+                ..Default::default()
+            };
+            journal.checkpoint(&item, &effects).unwrap();
+        }
+        let mut file = OpenOptions::new().append(true).open(&journal_path).unwrap();
+        io::Write::write_all(&mut file, br#"{"src":"in/"#).unwrap();
+
+        let summary = run(std::slice::from_ref(&item), &options(&dir), &journal_path);
+
+        assert_eq!(summary.resumed, 1);
+        assert_eq!(summary.skipped_existing, 1);
+        let journal = Journal::open_read_only(&journal_path).unwrap();
+        assert_eq!(journal.state(&item), ItemState::Done);
+        let entry = journal.entries_newest_first()[0];
+        assert_eq!(entry.effects.created.len(), 1);
+        let lines = fs::read_to_string(&journal_path).unwrap();
+        let torn = lines.lines().position(|l| l.ends_with(r#""in/"#)).unwrap();
+        assert!(
+            lines
+                .lines()
+                .skip(torn + 1)
+                .all(|l| serde_json::from_str::<crate::journal::JournalEntry>(l).is_ok())
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn retry_failed_reruns_only_failed_items() {
+        let dir = scratch("retry-failed");
+        let present = photo(&write(&dir.join("in/a.jpg"), "a"), &dir.join("out/a.jpg"));
+        let mut missing = photo(&dir.join("in/b.jpg"), &dir.join("out/b.jpg"));
+        missing.size_bytes = Some(1);
+        let items = [present, missing];
+        let journal = dir.join("journal.jsonl");
+
+        let first = run(&items, &options(&dir), &journal);
+        assert_eq!((first.copied, first.failed), (1, 1));
+
+        write(&dir.join("in/b.jpg"), "b");
+        let again = run(&items, &options(&dir), &journal);
+        assert_eq!((again.copied, again.previously_failed), (0, 1));
+
+        let retry = ApplyOptions {
+            retry_failed: true,
+            ..options(&dir)
+        };
+        let retried = run(&items, &retry, &journal);
+        assert_eq!((retried.copied, retried.skipped_journal), (1, 1));
+        assert_eq!(fs::read_to_string(dir.join("out/b.jpg")).unwrap(), "b");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn undo_puts_moved_and_quarantined_sources_back() {
+        for quarantine in [false, true] {
+            let dir = scratch(&format!("undo-move-{quarantine}"));
+            let src = write(&dir.join("in/one.jpg"), "one");
+            let mut item = photo(&src, &dir.join("out/one.jpg"));
+            item.action = Action::Move;
+            let options = ApplyOptions {
+                quarantine: quarantine.then(|| dir.join("quarantine")),
+                ..options(&dir)
+            };
+            let journal_path = dir.join("journal.jsonl");
+            let summary = run(std::slice::from_ref(&item), &options, &journal_path);
+            assert_eq!(summary.moved, 1);
+            assert!(!src.exists());
+
+            let mut journal = Journal::open(&journal_path).unwrap();
+            let undone = crate::undo::undo(&mut journal).unwrap();
+
+            assert_eq!((undone.undone, undone.sources_restored), (1, 1));
+            assert_eq!(fs::read_to_string(&src).unwrap(), "one");
+            assert!(!Path::new(&item.dst).exists());
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn undo_keeps_modified_and_replaced_outputs() {
+        let dir = scratch("undo-kept");
+        let items = [
+            photo(&write(&dir.join("in/a.jpg"), "a"), &dir.join("out/a.jpg")),
+            photo(&write(&dir.join("in/b.jpg"), "b"), &dir.join("out/b.jpg")),
+        ];
+        let [modified, replacing] = &items;
+        write(Path::new(&replacing.dst), "was here first");
+        let options = ApplyOptions {
+            conflicts: ConflictPolicy::Overwrite,
+            ..options(&dir)
+        };
+        let journal_path = dir.join("journal.jsonl");
+        let summary = run(&items, &options, &journal_path);
+        assert_eq!((summary.copied, summary.conflicts_overwritten), (2, 1));
+        write(Path::new(&modified.dst), "edited since");
+
+        let mut journal = Journal::open(&journal_path).unwrap();
+        let undone = crate::undo::undo(&mut journal).unwrap();
+
+        assert_eq!((undone.undone, undone.removed), (0, 0));
+        assert_eq!(undone.kept_modified, [modified.dst.as_str()]);
+        assert_eq!(undone.kept_replaced, [replacing.dst.as_str()]);
+        assert_eq!(fs::read_to_string(&replacing.dst).unwrap(), "b");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn release_source_keeps_a_source_its_copy_does_not_match() {
+        let dir = scratch("release-mismatch");
+        let src = write(&dir.join("in/one.jpg"), "one");
+        let copy = write(&dir.join("out/one.jpg"), "eno");
+        let journaled = Mutex::new(false);
+        let checkpoint = |_: &ItemEffects| -> Result<()> {
+            *journaled.lock().unwrap() = true;
+            Ok(())
+        };
+
+        assert!(release_source(&src, &copy, None, &checkpoint).is_err());
+        assert!(src.exists());
+        assert!(!*journaled.lock().unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn fail_and_overwrite_policies_apply_to_an_existing_destination() {
+        for (conflicts, expected) in [
+            (ConflictPolicy::Fail, "was here first"),
+            (ConflictPolicy::Overwrite, "one"),
+        ] {
+            let dir = scratch(&format!("policy-{conflicts:?}"));
+            let item = photo(
+                &write(&dir.join("in/one.jpg"), "one"),
+                &dir.join("out/one.jpg"),
+            );
+            write(Path::new(&item.dst), "was here first");
+            let options = ApplyOptions {
+                conflicts,
+                ..options(&dir)
+            };
+            let summary = run(std::slice::from_ref(&item), &options, &dir.join("j.jsonl"));
+
+            let failed = conflicts == ConflictPolicy::Fail;
+            assert_eq!(
+                (summary.failed, summary.copied),
+                (failed as u64, !failed as u64)
+            );
+            assert_eq!(fs::read_to_string(&item.dst).unwrap(), expected);
+            assert!(!numbered_path(Path::new(&item.dst), 1).exists());
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn resumed_move_only_trusts_outputs_the_journal_records() {
+        let dir = scratch("resumed-move");
+        let mut item = photo(
+            &write(&dir.join("in/one.jpg"), "one"),
+            &dir.join("out/one.jpg"),
+        );
+        item.action = Action::Move;
+        let journal_path = dir.join("journal.jsonl");
+        Journal::open(&journal_path).unwrap().start(&item).unwrap();
+        // The source is gone, but nothing says this run's move took it.
+        fs::remove_file(&item.src).unwrap();
+        write(Path::new(&item.dst), "someone else's");
+
+        let summary = run(std::slice::from_ref(&item), &options(&dir), &journal_path);
+
+        assert_eq!((summary.resumed, summary.failed), (1, 1));
+        assert_eq!(summary.skipped_existing, 0);
+        assert_eq!(fs::read_to_string(&item.dst).unwrap(), "someone else's");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn duplicate_links_to_where_an_earlier_run_put_its_canonical() {
+        let dir = scratch("dup-from-journal");
+        let canon = photo(
+            &write(&dir.join("in/a/x.jpg"), "same"),
+            &dir.join("out/x.jpg"),
+        );
+        let mut dup = photo(
+            &write(&dir.join("in/b/x.jpg"), "same"),
+            &dir.join("out/x_2.jpg"),
+        );
+        dup.duplicate_of = Some(canon.src.clone());
+        // An earlier run renamed the canonical around another file.
+        write(Path::new(&canon.dst), "someone else's");
+        let renamed = write(&numbered_path(Path::new(&canon.dst), 1), "same");
+        let journal_path = dir.join("journal.jsonl");
+        {
+            let mut journal = Journal::open(&journal_path).unwrap();
+            journal.start(&canon).unwrap();
+            let effects = ItemEffects {
+                written_to: Some(renamed.to_string_lossy().to_string()),
+                ..Default::default()
+            };
+            journal.finish(&canon, Ok(effects)).unwrap();
+        }
+        let options = ApplyOptions {
+            duplicates: DuplicatePolicy::Symlink,
+            ..options(&dir)
+        };
+
+        let summary = run(&[canon, dup], &options, &journal_path);
+
+        assert_eq!(summary.linked_duplicates, 1);
+        assert_eq!(fs::read_link(dir.join("out/x_2.jpg")).unwrap(), renamed);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn duplicates_with_the_canonical_name_are_linked() {
+        use crate::plan::{InputRoot, NamingMode, PlanOptions, build_plan};
+        use std::os::unix::fs::MetadataExt;
+
+        for naming in [NamingMode::Original, NamingMode::Timestamp] {
+            let dir =
+                std::env::temp_dir().join(format!("mo-dup-link-{naming:?}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            for sub in ["a", "b"] {
+                fs::create_dir_all(dir.join("in").join(sub)).unwrap();
+                fs::write(dir.join("in").join(sub).join("same.jpg"), "identical").unwrap();
+            }
+            let root: InputRoot = dir.join("in").to_string_lossy().parse().unwrap();
+            let plan_options = PlanOptions {
+                naming,
+                ..Default::default()
+            };
+            let (items, _) = build_plan(
+                &[root],
+                &dir.join("out"),
+                &plan_options,
+                &std::sync::Arc::new(Progress::new(false)),
+            )
+            .unwrap();
+            let dup = items.iter().find(|i| i.duplicate_of.is_some()).unwrap();
+            let canon = items.iter().find(|i| i.duplicate_of.is_none()).unwrap();
+            assert_ne!(dup.dst, canon.dst);
+
+            let options = ApplyOptions {
+                duplicates: DuplicatePolicy::Hardlink,
+                ..options(&dir)
+            };
+            let mut journal = Journal::open(&dir.join("journal.jsonl")).unwrap();
+            let summary =
+                apply_items(&items, &options, &mut journal, &Progress::new(false)).unwrap();
+
+            assert_eq!(summary.linked_duplicates, 1);
+            let ino = |p: &str| fs::metadata(p).unwrap().ino();
+            assert_eq!(ino(&dup.dst), ino(&canon.dst));
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn items_sharing_a_destination_are_both_kept() {
+        let (dir, items) = same_destination("shared-dst");
+        let mut journal = Journal::open(&dir.join("journal.jsonl")).unwrap();
+        let summary =
+            apply_items(&items, &options(&dir), &mut journal, &Progress::new(false)).unwrap();
+
+        assert_eq!(summary.copied, 2);
+        assert_eq!(summary.conflicts_renamed, 1);
+        let dst = dir.join("out/two.jpg");
+        let mut contents: Vec<String> = [dst.clone(), numbered_path(&dst, 1)]
+            .iter()
+            .map(|p| fs::read_to_string(p).unwrap())
+            .collect();
+        contents.sort();
+        assert_eq!(contents, ["alpha", "beta, longer"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dry_run_sees_destinations_earlier_items_would_fill() {
+        let (dir, items) = same_destination("dry-run-dst");
+        let options = ApplyOptions {
+            dry_run: true,
+            copy_jobs: 1,
+            ..options(&dir)
+        };
+        let mut journal = Journal::open_read_only(&dir.join("journal.jsonl")).unwrap();
+        let summary = apply_items(&items, &options, &mut journal, &Progress::new(false)).unwrap();
+
+        assert_eq!(summary.copied, 2);
+        assert_eq!(summary.conflicts_renamed, 1);
+        assert!(!dir.join("out").exists());
+        assert!(!dir.join("journal.jsonl").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,199 @@
+use crate::apply::{Conflict, DuplicatePolicy, Outcome};
+use crate::journal::ReleasedSource;
+use crate::plan::Action;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// One line of an apply run's event log: what happened to one item.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApplyEvent {
+    pub run_id: String,
+    /// Position of the item in the manifest.
+    pub index: usize,
+    pub src: String,
+    pub dst: String,
+    pub action: Action,
+    /// How the item was placed, for exact duplicates.
+    pub duplicate_policy: Option<DuplicatePolicy>,
+    pub outcome: Outcome,
+    /// Content the item was matched to: its canonical output or library file.
+    pub target: Option<String>,
+    /// Set when the destination already held different content.
+    pub conflict: Option<Conflict>,
+    /// Where the item actually went, when not `dst`.
+    pub written_to: Option<String>,
+    /// blake3 of the output, when the copy was verified.
+    pub hash: Option<String>,
+    /// Size of the output this run wrote.
+    pub bytes: Option<u64>,
+    pub duration_ms: u64,
+    pub released: Option<ReleasedSource>,
+    /// Error chain of a failed item, outermost context first.
+    pub error: Vec<String>,
+    /// Problems that did not fail the item, such as an unwritable sidecar.
+    pub warnings: Vec<String>,
+    /// ffmpeg commands a dry run would have run for the item.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub commands: Vec<String>,
+    pub finished: String,
+}
+
+/// Sorts by start time, so the newest log in a directory is the last one.
+pub fn new_run_id() -> String {
+    format!(
+        "{}-{}",
+        chrono::Local::now().format("%Y%m%d-%H%M%S"),
+        std::process::id()
+    )
+}
+
+/// `Outcome::SkippedExisting` -> `skipped_existing`, as in the log.
+pub fn outcome_name(outcome: Outcome) -> String {
+    serde_json::to_value(outcome)
+        .ok()
+        .and_then(|v| v.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+/// One readable line per event, with any details indented below it.
+fn describe(event: &ApplyEvent) -> String {
+    let mut text = format!(
+        "{:18} {} -> {}",
+        outcome_name(event.outcome),
+        event.src,
+        event.dst
+    );
+    let mut detail = |line: String| text.push_str(&format!("\n    {line}"));
+    if let Some(written) = &event.written_to {
+        detail(format!("renamed to {written}"));
+    } else if event.conflict == Some(Conflict::Overwritten) {
+        detail("replaces the existing file".into());
+    }
+    if let Some(target) = &event.target {
+        match event.duplicate_policy {
+            Some(policy) => detail(format!("{policy:?} duplicate of {target}")),
+            None => detail(format!("already in library as {target}")),
+        }
+    }
+    if let Some(released) = &event.released {
+        match &released.quarantined_to {
+            Some(q) => detail(format!("source quarantined to {q}")),
+            None => detail("source removed".into()),
+        }
+    }
+    for command in &event.commands {
+        detail(format!("$ {command}"));
+    }
+    for cause in &event.error {
+        detail(format!("error: {cause}"));
+    }
+    for warning in &event.warnings {
+        detail(format!("warning: {warning}"));
+    }
+    text
+}
+
+pub fn log_path(log_dir: &Path, run_id: &str) -> PathBuf {
+    log_dir.join(format!("apply-{run_id}.jsonl"))
+}
+
+/// The most recent `apply-*.jsonl` in `log_dir`.
+pub fn latest_log(log_dir: &Path) -> Result<PathBuf> {
+    let mut logs: Vec<PathBuf> = fs::read_dir(log_dir)
+        .with_context(|| format!("read log dir {}", log_dir.display()))?
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| {
+            p.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with("apply-") && n.ends_with(".jsonl"))
+        })
+        .collect();
+    logs.sort();
+    logs.pop()
+        .with_context(|| format!("no apply logs in {}", log_dir.display()))
+}
+
+pub fn read_events(path: &Path) -> Result<Vec<ApplyEvent>> {
+    let file = File::open(path).with_context(|| format!("open apply log {}", path.display()))?;
+    let mut events = Vec::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event = serde_json::from_str(&line)
+            .with_context(|| format!("parse {} line {}", path.display(), i + 1))?;
+        events.push(event);
+    }
+    Ok(events)
+}
+
+/// Writes one run's events in manifest order, however the workers finish.
+pub struct EventLog {
+    /// `None` prints readable lines to stdout instead, for a dry run.
+    file: Option<File>,
+    /// Manifest index of the next item to be written.
+    next: usize,
+    /// Finished items waiting on an earlier one still in flight. `None`
+    /// holds the place of an item the run did not touch.
+    pending: BTreeMap<usize, Option<ApplyEvent>>,
+}
+
+impl EventLog {
+    pub fn create(path: &Path) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).with_context(|| format!("create log dir {}", dir.display()))?;
+        }
+        let file =
+            File::create(path).with_context(|| format!("create apply log {}", path.display()))?;
+        Ok(Self {
+            file: Some(file),
+            next: 0,
+            pending: BTreeMap::new(),
+        })
+    }
+
+    pub fn stdout() -> Self {
+        Self {
+            file: None,
+            next: 0,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    fn write(&mut self, event: Option<&ApplyEvent>) -> Result<()> {
+        match (event, &mut self.file) {
+            (Some(event), Some(file)) => writeln!(file, "{}", serde_json::to_string(event)?)?,
+            (Some(event), None) => println!("{}", describe(event)),
+            (None, _) => {}
+        }
+        Ok(())
+    }
+
+    /// Queues the event of the item at manifest `index` and writes every
+    /// event now in order.
+    pub fn push(&mut self, index: usize, event: Option<ApplyEvent>) -> Result<()> {
+        self.pending.insert(index, event);
+        while let Some(event) = self.pending.remove(&self.next) {
+            self.write(event.as_ref())?;
+            self.next += 1;
+        }
+        Ok(())
+    }
+
+    /// Writes whatever is still queued, e.g. after a fatal error left a gap.
+    pub fn flush_pending(&mut self) -> Result<()> {
+        for event in std::mem::take(&mut self.pending).into_values() {
+            self.write(event.as_ref())?;
+        }
+        if let Some(file) = &mut self.file {
+            file.flush()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+use crate::apply::ensure_parent_dir;
+use anyhow::{Context, Result};
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Outputs are written under this prefix next to their destination and
+/// renamed into place once complete, so an interrupted run never leaves a
+/// truncated file under the final name.
+const TEMP_PREFIX: &str = ".mo-tmp-";
+
+/// Whether `name` is one of apply's temp files.
+pub fn is_temp_name(name: &str) -> bool {
+    name.starts_with(TEMP_PREFIX)
+}
+
+static NEXT_TEMP: AtomicUsize = AtomicUsize::new(0);
+
+/// A fresh name on every call, so concurrent writes to one destination never
+/// share a temp file. Keeps the destination's extension so ffmpeg still
+/// picks the right muxer.
+pub fn temp_path(dst: &Path) -> PathBuf {
+    let name = dst
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let n = NEXT_TEMP.fetch_add(1, Ordering::Relaxed);
+    dst.with_file_name(format!("{TEMP_PREFIX}{}-{n}-{name}", std::process::id()))
+}
+
+fn sync_dir(dir: &Path) -> Result<()> {
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+/// Runs `write` against a temp path beside `dst`, then fsyncs and renames the
+/// result into place. On failure the temp file is removed and `dst` is left
+/// untouched.
+pub fn write_atomically(dst: &Path, write: impl FnOnce(&Path) -> Result<()>) -> Result<()> {
+    ensure_parent_dir(dst)?;
+    let tmp = temp_path(dst);
+
+    let result = write(&tmp).and_then(|()| {
+        File::open(&tmp)
+            .and_then(|f| f.sync_all())
+            .with_context(|| format!("sync {}", tmp.display()))?;
+        fs::rename(&tmp, dst)
+            .with_context(|| format!("rename {} -> {}", tmp.display(), dst.display()))?;
+        if let Some(parent) = dst.parent() {
+            sync_dir(parent)?;
+        }
+        Ok(())
+    });
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// The pid in a temp file's name, `.mo-tmp-<pid>-...`.
+fn temp_owner(name: &str) -> Option<u32> {
+    name.strip_prefix(TEMP_PREFIX)?
+        .split('-')
+        .next()?
+        .parse()
+        .ok()
+}
+
+#[cfg(target_os = "linux")]
+fn process_alive(pid: u32) -> bool {
+    Path::new(&format!("/proc/{pid}")).exists()
+}
+
+/// Without a cheap way to tell, another process's temps are left alone.
+#[cfg(not(target_os = "linux"))]
+fn process_alive(_pid: u32) -> bool {
+    true
+}
+
+/// Deletes temp files left by interrupted runs in the directories `dsts`
+/// would be written to. Temps of another apply still running on the same
+/// library are kept. Returns how many were removed.
+pub fn clean_stale_temps<'a>(dsts: impl IntoIterator<Item = &'a Path>) -> usize {
+    let dirs: BTreeSet<&Path> = dsts.into_iter().filter_map(Path::parent).collect();
+    let own_pid = std::process::id();
+
+    let mut removed = 0;
+    for dir in dirs {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !is_temp_name(&name) {
+                continue;
+            }
+            // This run has written nothing yet, so its own pid means an
+            // earlier run that happened to get the same one.
+            let stale = match temp_owner(&name) {
+                Some(pid) => pid == own_pid || !process_alive(pid),
+                None => true,
+            };
+            if stale && fs::remove_file(entry.path()).is_ok() {
+                removed += 1;
+            }
+        }
+    }
+    removed
+}
@@ -0,0 +1,49 @@
+use anyhow::{Context, Result};
+use std::process::{Command, ExitStatus};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// How often a running child process checks whether to stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The first Ctrl-C asks apply to stop after cleaning up; a second one
+/// exits at once.
+pub fn install_handler() -> Result<()> {
+    ctrlc::set_handler(|| {
+        if REQUESTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(130);
+        }
+        eprintln!("\nCancelling: stopping in-flight work (press Ctrl-C again to quit now)");
+    })
+    .context("install Ctrl-C handler")
+}
+
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Fails once cancellation has been requested.
+pub fn check() -> Result<()> {
+    anyhow::ensure!(!requested(), "cancelled");
+    Ok(())
+}
+
+/// Like `Command::status`, but kills the child if cancellation is requested
+/// while it runs.
+pub fn run(cmd: &mut Command) -> Result<ExitStatus> {
+    let mut child = cmd.spawn()?;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if requested() {
+            let _ = child.kill();
+            child.wait()?;
+            anyhow::bail!("cancelled");
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
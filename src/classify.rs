@@ -1,6 +1,6 @@
 use std::path::Path;
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Copy, Serialize)]
 pub enum Kind {
@@ -9,18 +9,51 @@ pub enum Kind {
     Ignore,
 }
 
+/// Lowercase extensions (without the dot) recognised as each kind of media.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExtensionMap {
+    pub photo: Vec<String>,
+    pub video: Vec<String>,
+    /// Video extensions that are re-encoded to mp4 rather than copied.
+    pub convert_video: Vec<String>,
+}
+
+impl Default for ExtensionMap {
+    fn default() -> Self {
+        Self {
+            photo: vec!["jpg".into(), "jpeg".into(), "png".into()],
+            video: vec!["mp4".into(), "avi".into(), "mov".into(), "m4v".into()],
+            convert_video: vec!["avi".into()],
+        }
+    }
+}
+
+impl ExtensionMap {
+    fn contains(list: &[String], ext: Option<&str>) -> bool {
+        ext.is_some_and(|e| list.iter().any(|x| x.eq_ignore_ascii_case(e)))
+    }
+
+    pub fn needs_conversion(&self, path: &Path) -> bool {
+        Self::contains(&self.convert_video, normalize_extension(path).as_deref())
+    }
+}
+
 pub fn normalize_extension(path: &Path) -> Option<String> {
     path.extension()
         .and_then(|e| e.to_str())
         .map(|s| s.to_ascii_lowercase())
 }
 
-pub fn classify(path: &Path) -> Kind {
+pub fn classify(path: &Path, extensions: &ExtensionMap) -> Kind {
     let extension = normalize_extension(path);
-    match extension.as_deref() {
-        Some("jpg") | Some("jpeg") | Some("png") => Kind::Photo,
-        Some("mp4") | Some("avi") | Some("mov") | Some("m4v") => Kind::Video,
-        _ => Kind::Ignore,
+    let ext = extension.as_deref();
+    if ExtensionMap::contains(&extensions.photo, ext) {
+        Kind::Photo
+    } else if ExtensionMap::contains(&extensions.video, ext) {
+        Kind::Video
+    } else {
+        Kind::Ignore
     }
 }
 
@@ -0,0 +1,276 @@
+use crate::apply::{ConflictPolicy, DuplicatePolicy};
+use crate::config::Config;
+use crate::plan::{InputRoot, NamingMode};
+use crate::preflight::SpaceCheck;
+use clap::{Args, Parser, Subcommand};
+use clap_complete::Shell;
+use std::path::PathBuf;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "media_organizer",
+    version,
+    about = "Organise photos, videos and DVDs into a dated library"
+)]
+pub struct Cli {
+    /// Config file to use instead of ./media_organizer.toml
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Scan an input tree and write a manifest of planned copies and conversions
+    Plan(PlanArgs),
+    /// Execute a manifest written by `plan`
+    Apply(ApplyArgs),
+    /// Summarise a manifest, optionally checking that outputs were written
+    Report(ReportArgs),
+    /// Remove the outputs an apply created and restore the sources it moved
+    Undo {
+        /// Journal written by `apply`, e.g. manifest.journal.jsonl
+        journal: PathBuf,
+    },
+    /// Print a shell completion script to stdout
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+}
+
+#[derive(Debug, Args)]
+pub struct PlanArgs {
+    /// Directories to scan for media, as PATH or LABEL=PATH [default: .]
+    #[arg(value_name = "INPUT_ROOT")]
+    pub input_roots: Vec<InputRoot>,
+
+    /// Library root that planned outputs are placed under [default: ./ExportSet]
+    #[arg(short, long)]
+    pub out_root: Option<PathBuf>,
+
+    /// Where to write the manifest [default: manifest.jsonl]
+    #[arg(short, long)]
+    pub manifest: Option<PathBuf>,
+
+    /// How output files are named [default: original]
+    #[arg(long, value_enum)]
+    pub naming: Option<NamingMode>,
+
+    /// Remove each source after apply has copied and verified it (conversions keep theirs)
+    #[arg(long = "move")]
+    pub move_sources: bool,
+
+    /// Do not check whether inputs already exist in the output library
+    #[arg(long)]
+    pub no_library_check: bool,
+
+    /// Group visually similar photos as near-duplicates (decodes every photo)
+    #[arg(long)]
+    pub near_duplicates: bool,
+
+    /// Maximum differing hash bits (of 64) for near-duplicate photos [default: 10]
+    #[arg(long, value_name = "BITS")]
+    pub near_threshold: Option<u32>,
+
+    /// Group the same footage across encodings as near-duplicates (runs ffmpeg per video)
+    #[arg(long)]
+    pub near_video_duplicates: bool,
+
+    /// Maximum mean differing bits per sampled frame for near-duplicate videos [default: 12]
+    #[arg(long, value_name = "BITS")]
+    pub near_video_threshold: Option<u32>,
+
+    /// Record a content hash for every photo and video in the manifest
+    #[arg(long)]
+    pub hash_all: bool,
+
+    /// Hash cache file to read and update [default: ~/.cache/media_organizer/hashes.jsonl]
+    #[arg(long, value_name = "PATH")]
+    pub hash_cache: Option<PathBuf>,
+
+    /// Hash every file afresh without reading or writing the hash cache
+    #[arg(long)]
+    pub no_hash_cache: bool,
+
+    /// Worker threads for metadata and hashing [default: one per CPU]
+    #[arg(short = 'j', long)]
+    pub threads: Option<usize>,
+
+    /// Maximum ffprobe processes at once [default: 4]
+    #[arg(long, value_name = "N")]
+    pub ffprobe_jobs: Option<usize>,
+
+    /// Do not show live progress on stderr
+    #[arg(long)]
+    pub no_progress: bool,
+
+    /// Only plan files matching this glob (repeatable, added to the config list)
+    #[arg(long, value_name = "GLOB")]
+    pub include: Vec<String>,
+
+    /// Skip files and directories matching this glob (repeatable, added to the config list)
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+
+    /// Ignore `.mediaignore` files
+    #[arg(long)]
+    pub no_mediaignore: bool,
+}
+
+impl PlanArgs {
+    pub fn override_config(self, config: &mut Config) {
+        if !self.input_roots.is_empty() {
+            config.input_roots = self.input_roots;
+        }
+        config.out_root = self.out_root.or(config.out_root.take());
+        config.manifest = self.manifest.or(config.manifest.take());
+        if let Some(naming) = self.naming {
+            config.naming = naming;
+        }
+        if self.no_library_check {
+            config.duplicates.against_library = false;
+        }
+        config.duplicates.near_photos |= self.near_duplicates;
+        if let Some(threshold) = self.near_threshold {
+            config.duplicates.near_threshold = threshold;
+        }
+        config.duplicates.near_videos |= self.near_video_duplicates;
+        if let Some(threshold) = self.near_video_threshold {
+            config.duplicates.near_video_threshold = threshold;
+        }
+        config.duplicates.hash_all |= self.hash_all;
+        config.move_sources |= self.move_sources;
+        if let Some(path) = self.hash_cache {
+            config.hash_cache.path = Some(path);
+        }
+        if self.no_hash_cache {
+            config.hash_cache.enabled = false;
+        }
+        if let Some(threads) = self.threads {
+            config.parallel.threads = threads;
+        }
+        if let Some(n) = self.ffprobe_jobs {
+            config.parallel.ffprobe_processes = n;
+        }
+        config.scan.include.extend(self.include);
+        config.scan.exclude.extend(self.exclude);
+        if self.no_mediaignore {
+            config.scan.mediaignore = false;
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct ApplyArgs {
+    /// Manifest to apply [default: manifest.jsonl]
+    #[arg(short, long)]
+    pub manifest: Option<PathBuf>,
+
+    /// Directory the apply event log is written to [default: .]
+    #[arg(long)]
+    pub log_dir: Option<PathBuf>,
+
+    /// Write an `.xmp` sidecar with the original filename next to renamed outputs
+    #[arg(long, overrides_with = "no_xmp_sidecar")]
+    pub xmp_sidecar: bool,
+
+    /// Do not write `.xmp` sidecars, even if the config file asks for them
+    #[arg(long, overrides_with = "xmp_sidecar")]
+    pub no_xmp_sidecar: bool,
+
+    /// Journal recording per-item progress [default: <manifest>.journal.jsonl]
+    #[arg(long, value_name = "PATH")]
+    pub journal: Option<PathBuf>,
+
+    /// Only reprocess items that failed in an earlier run
+    #[arg(long)]
+    pub retry_failed: bool,
+
+    /// Show what apply would do to each item without writing, moving or
+    /// encoding anything
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Trust the copy without re-reading it to compare hashes
+    #[arg(long)]
+    pub no_verify: bool,
+
+    /// Move sources of verified moves here instead of deleting them
+    #[arg(long, value_name = "DIR")]
+    pub quarantine: Option<PathBuf>,
+
+    /// How exact duplicates are placed in the library [default: skip]
+    #[arg(long, value_enum, value_name = "POLICY")]
+    pub duplicates: Option<DuplicatePolicy>,
+
+    /// What to do when a destination exists with different content [default: rename]
+    #[arg(long, value_enum, value_name = "POLICY")]
+    pub conflicts: Option<ConflictPolicy>,
+
+    /// Copies, moves and links running at once [default: 4]
+    #[arg(long, value_name = "N")]
+    pub copy_jobs: Option<usize>,
+
+    /// Video and DVD conversions running at once [default: 1]
+    #[arg(long, value_name = "N")]
+    pub ffmpeg_jobs: Option<usize>,
+
+    /// What to do if the output volume looks too small or is not writable [default: fail]
+    #[arg(long, value_enum, value_name = "MODE")]
+    pub space_check: Option<SpaceCheck>,
+
+    /// Do not show live progress on stderr
+    #[arg(long)]
+    pub no_progress: bool,
+}
+
+impl ApplyArgs {
+    pub fn override_config(self, config: &mut Config) {
+        config.manifest = self.manifest.or(config.manifest.take());
+        config.log_dir = self.log_dir.or(config.log_dir.take());
+        if self.xmp_sidecar {
+            config.xmp_sidecars = true;
+        } else if self.no_xmp_sidecar {
+            config.xmp_sidecars = false;
+        }
+        config.apply.quarantine = self.quarantine.or(config.apply.quarantine.take());
+        config.apply.journal = self.journal.or(config.apply.journal.take());
+        if self.no_verify {
+            config.apply.verify_copies = false;
+        }
+        if let Some(policy) = self.duplicates {
+            config.duplicates.policy = policy;
+        }
+        if let Some(policy) = self.conflicts {
+            config.apply.conflicts = policy;
+        }
+        if let Some(n) = self.copy_jobs {
+            config.parallel.copy_jobs = n;
+        }
+        if let Some(n) = self.ffmpeg_jobs {
+            config.parallel.ffmpeg_jobs = n;
+        }
+        if let Some(mode) = self.space_check {
+            config.apply.space_check = mode;
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct ReportArgs {
+    /// Manifest to report on [default: manifest.jsonl]
+    #[arg(short, long)]
+    pub manifest: Option<PathBuf>,
+
+    /// Check that every non-duplicate output exists, is non-empty and matches its planned or
+    /// journaled hash
+    #[arg(long)]
+    pub validate_outputs: bool,
+
+    /// Summarise an apply event log; given a directory, its newest log is used
+    #[arg(long, value_name = "PATH")]
+    pub apply_log: Option<PathBuf>,
+}
@@ -0,0 +1,239 @@
+use crate::apply::{ConflictPolicy, DuplicatePolicy};
+use crate::classify::ExtensionMap;
+use crate::filter::ScanSettings;
+use crate::hash_cache::default_cache_path;
+use crate::plan::{InputRoot, LayoutTemplates, NamingMode};
+use crate::preflight::SpaceCheck;
+use crate::rank::RankPolicy;
+use crate::video::EncodingProfile;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use toml::Table;
+
+pub const PROJECT_CONFIG_FILE: &str = "media_organizer.toml";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EncodingSettings {
+    pub video: EncodingProfile,
+    pub dvd: EncodingProfile,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DuplicateSettings {
+    /// Hash same-size inputs and mark byte-identical copies as duplicates.
+    pub detect: bool,
+    /// Skip inputs whose content already exists under the output root.
+    pub against_library: bool,
+    /// Decode photos and group visually identical ones as near-duplicates.
+    pub near_photos: bool,
+    /// Maximum differing bits (of 64) for two photos to count as near-duplicates.
+    pub near_threshold: u32,
+    /// Sample frames from videos with ffmpeg and group the same footage across
+    /// different encodings as near-duplicates.
+    pub near_videos: bool,
+    /// Maximum mean differing bits per sampled frame for two clips to match.
+    pub near_video_threshold: u32,
+    /// Which copy of a duplicate group is kept.
+    pub rank: RankPolicy,
+    /// Record `content_hash` for every photo and video, not only duplicates.
+    pub hash_all: bool,
+    /// How apply places exact duplicates in the library.
+    pub policy: DuplicatePolicy,
+}
+
+impl Default for DuplicateSettings {
+    fn default() -> Self {
+        Self {
+            detect: true,
+            against_library: true,
+            near_photos: false,
+            near_threshold: 10,
+            near_videos: false,
+            near_video_threshold: 12,
+            rank: RankPolicy::default(),
+            hash_all: false,
+            policy: DuplicatePolicy::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheSettings {
+    pub enabled: bool,
+    /// Defaults to `~/.cache/media_organizer/hashes.jsonl`.
+    pub path: Option<PathBuf>,
+}
+
+impl Default for CacheSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            path: None,
+        }
+    }
+}
+
+impl CacheSettings {
+    pub fn effective_path(&self) -> Option<PathBuf> {
+        if !self.enabled {
+            return None;
+        }
+        self.path.clone().or_else(default_cache_path)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ParallelSettings {
+    /// Worker threads for file I/O and hashing; 0 means one per CPU.
+    pub threads: usize,
+    /// Maximum ffprobe processes at once.
+    pub ffprobe_processes: usize,
+    /// Apply workers for copies, moves and duplicate links.
+    pub copy_jobs: usize,
+    /// Apply workers for video and DVD conversions, so a long transcode
+    /// does not hold up the copies queued behind it.
+    pub ffmpeg_jobs: usize,
+}
+
+impl Default for ParallelSettings {
+    fn default() -> Self {
+        Self {
+            threads: 0,
+            ffprobe_processes: 4,
+            copy_jobs: 4,
+            ffmpeg_jobs: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApplySettings {
+    /// Re-read each copied output and compare its hash with the source.
+    pub verify_copies: bool,
+    /// Moved sources are put here instead of being deleted.
+    pub quarantine: Option<PathBuf>,
+    /// Defaults to the manifest path with a `.journal.jsonl` extension.
+    pub journal: Option<PathBuf>,
+    /// What to do when a destination exists with different content.
+    pub conflicts: ConflictPolicy,
+    /// What to do when `out_root` lacks the space or permissions apply needs.
+    pub space_check: SpaceCheck,
+    /// Bitrate assumed for converted videos when estimating their size.
+    pub estimated_video_kbps: u32,
+}
+
+impl Default for ApplySettings {
+    fn default() -> Self {
+        Self {
+            verify_copies: true,
+            quarantine: None,
+            journal: None,
+            conflicts: ConflictPolicy::Rename,
+            space_check: SpaceCheck::Fail,
+            estimated_video_kbps: 8000,
+        }
+    }
+}
+
+/// Settings shared by `plan`, `apply` and `report`. Loaded from the user config,
+/// then the project config, then overridden by command-line flags.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub input_roots: Vec<InputRoot>,
+    pub out_root: Option<PathBuf>,
+    pub manifest: Option<PathBuf>,
+    pub log_dir: Option<PathBuf>,
+    pub naming: NamingMode,
+    pub xmp_sidecars: bool,
+    /// Plan copies as moves that remove each source once its copy verifies.
+    pub move_sources: bool,
+    pub extensions: ExtensionMap,
+    pub scan: ScanSettings,
+    pub layout: LayoutTemplates,
+    pub encoding: EncodingSettings,
+    pub duplicates: DuplicateSettings,
+    pub hash_cache: CacheSettings,
+    pub parallel: ParallelSettings,
+    pub apply: ApplySettings,
+}
+
+impl Config {
+    pub fn input_roots(&self) -> Vec<InputRoot> {
+        if self.input_roots.is_empty() {
+            // Labelled like `.` given on the command line.
+            vec![".".parse().expect("`.` is a valid input root")]
+        } else {
+            self.input_roots.clone()
+        }
+    }
+
+    pub fn out_root(&self) -> PathBuf {
+        self.out_root
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("./ExportSet"))
+    }
+
+    pub fn manifest(&self) -> PathBuf {
+        self.manifest
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("manifest.jsonl"))
+    }
+
+    pub fn log_dir(&self) -> PathBuf {
+        self.log_dir.clone().unwrap_or_else(|| PathBuf::from("."))
+    }
+}
+
+pub fn user_config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|d| d.join("media_organizer").join("config.toml"))
+}
+
+fn read_table(path: &Path) -> Result<Table> {
+    let text =
+        std::fs::read_to_string(path).with_context(|| format!("read config {}", path.display()))?;
+    text.parse::<Table>()
+        .with_context(|| format!("parse config {}", path.display()))
+}
+
+/// Later tables win; nested tables are merged key by key rather than replaced.
+fn merge_tables(base: &mut Table, overlay: Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(b)), toml::Value::Table(o)) => merge_tables(b, o),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Loads the user config and the project config (or `explicit`, which replaces
+/// the project config). Missing files are skipped; malformed ones are errors.
+pub fn load(explicit: Option<&Path>) -> Result<Config> {
+    let mut merged = Table::new();
+
+    if let Some(user) = user_config_path().filter(|p| p.is_file()) {
+        merge_tables(&mut merged, read_table(&user)?);
+    }
+
+    match explicit {
+        Some(path) => merge_tables(&mut merged, read_table(path)?),
+        None => {
+            let project = Path::new(PROJECT_CONFIG_FILE);
+            if project.is_file() {
+                merge_tables(&mut merged, read_table(project)?);
+            }
+        }
+    }
+
+    merged.try_into().context(
+        "invalid configuration (see media_organizer.toml / ~/.config/media_organizer/config.toml)",
+    )
+}
@@ -1,12 +1,16 @@
+use crate::atomic;
+use crate::hash_cache::HashCache;
 use anyhow::Result;
+use rayon::prelude::*;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fs::File,
-    io::{self, Read},
+    io::{self, Read, Seek, SeekFrom},
     path::{Path, PathBuf},
 };
+use walkdir::WalkDir;
 
-fn blake3_hash_file(path: &Path) -> io::Result<blake3::Hash> {
+pub fn blake3_hash_file(path: &Path) -> io::Result<blake3::Hash> {
     let mut f = File::open(path)?;
     let mut hasher = blake3::Hasher::new();
     let mut buf = [0u8; 1024 * 1024];
@@ -22,32 +26,170 @@ fn blake3_hash_file(path: &Path) -> io::Result<blake3::Hash> {
     Ok(hasher.finalize())
 }
 
-pub fn find_exact_duplicates(paths: &[PathBuf]) -> Result<HashMap<String, Vec<PathBuf>>> {
+/// Bytes read from each end of a file for its sample hash.
+const SAMPLE_BYTES: u64 = 64 * 1024;
+
+/// Cheap pre-filter for same-size files: blake3 over the first and last
+/// `SAMPLE_BYTES`. Files whose samples differ cannot be identical, so only
+/// matching samples need a full read.
+pub fn sample_hash_file(path: &Path, size: u64) -> io::Result<blake3::Hash> {
+    let mut f = File::open(path)?;
+    let mut hasher = blake3::Hasher::new();
+    let mut buf = vec![0u8; SAMPLE_BYTES as usize];
+
+    let head = &mut buf[..size.min(SAMPLE_BYTES) as usize];
+    f.read_exact(head)?;
+    hasher.update(head);
+
+    if size > 2 * SAMPLE_BYTES {
+        f.seek(SeekFrom::End(-(SAMPLE_BYTES as i64)))?;
+        f.read_exact(&mut buf)?;
+        hasher.update(&buf);
+    }
+
+    Ok(hasher.finalize())
+}
+
+/// Keeps only the files sharing a size and sample hash with another file.
+/// Small files are passed through untouched since sampling them costs as
+/// much as hashing them in full.
+fn filter_by_sample(by_size: HashMap<u64, Vec<PathBuf>>) -> io::Result<Vec<PathBuf>> {
+    let (small, large): (Vec<_>, Vec<_>) = by_size
+        .into_iter()
+        .filter(|(_, group)| group.len() > 1)
+        .partition(|(size, _)| *size <= 2 * SAMPLE_BYTES);
+
+    let sampled: Vec<((u64, blake3::Hash), PathBuf)> = large
+        .into_iter()
+        .flat_map(|(size, group)| group.into_iter().map(move |p| (size, p)))
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .map(|(size, p)| sample_hash_file(&p, size).map(|h| ((size, h), p)))
+        .collect::<io::Result<_>>()?;
+
+    let mut by_sample: HashMap<(u64, blake3::Hash), Vec<PathBuf>> = HashMap::new();
+    for (key, p) in sampled {
+        by_sample.entry(key).or_default().push(p);
+    }
+
+    Ok(small
+        .into_iter()
+        .map(|(_, group)| group)
+        .chain(by_sample.into_values().filter(|group| group.len() > 1))
+        .flatten()
+        .collect())
+}
+
+pub fn find_exact_duplicates(
+    paths: &[PathBuf],
+    cache: &HashCache,
+) -> Result<HashMap<String, Vec<PathBuf>>> {
     let mut by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
     for p in paths {
         let size = std::fs::metadata(p).map(|m| m.len()).unwrap_or(0);
         by_size.entry(size).or_default().push(p.clone());
     }
 
-    let mut dup_groups: HashMap<String, Vec<PathBuf>> = HashMap::new();
+    let to_hash = filter_by_sample(by_size)?;
+    cache.start_stage("Hashing duplicates", &to_hash);
+    let hashed: Vec<(PathBuf, String)> = to_hash
+        .into_par_iter()
+        .map(|p| cache.hash(&p).map(|h| (p, h)))
+        .collect::<io::Result<_>>()?;
 
-    for (_size, group) in by_size {
-        if group.len() < 2 {
-            continue;
+    let mut by_hash: HashMap<String, Vec<PathBuf>> = HashMap::new();
+    for (p, h) in hashed {
+        by_hash.entry(h).or_default().push(p);
+    }
+    by_hash.retain(|_, g| g.len() > 1);
+
+    Ok(by_hash)
+}
+
+/// Finds inputs whose exact content already exists somewhere under
+/// `library_root`. Only library files sharing a size with some input are
+/// hashed; apply's temp files and `.xmp` sidecars are not library content,
+/// and unreadable files are skipped with a warning. Returns input path ->
+/// matching library path.
+pub fn find_library_matches(
+    paths: &[PathBuf],
+    library_root: &Path,
+    cache: &HashCache,
+) -> Result<HashMap<PathBuf, PathBuf>> {
+    let mut matches: HashMap<PathBuf, PathBuf> = HashMap::new();
+    if !library_root.is_dir() {
+        return Ok(matches);
+    }
+
+    let mut inputs_by_size: HashMap<u64, Vec<&PathBuf>> = HashMap::new();
+    for p in paths {
+        if let Ok(m) = std::fs::metadata(p) {
+            inputs_by_size.entry(m.len()).or_default().push(p);
         }
+    }
 
-        let mut by_hash: HashMap<String, Vec<PathBuf>> = HashMap::new();
-        for p in group {
-            let h = blake3_hash_file(&p)?.to_hex().to_string();
-            by_hash.entry(h).or_default().push(p);
+    let mut library_by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    for entry in WalkDir::new(library_root)
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        if !entry.file_type().is_file()
+            || atomic::is_temp_name(&entry.file_name().to_string_lossy())
+            || entry
+                .path()
+                .extension()
+                .is_some_and(|e| e.eq_ignore_ascii_case("xmp"))
+        {
+            continue;
+        }
+        let Ok(m) = entry.metadata() else {
+            continue;
+        };
+        if inputs_by_size.contains_key(&m.len()) {
+            library_by_size
+                .entry(m.len())
+                .or_default()
+                .push(entry.into_path());
         }
+    }
 
-        for (h, g) in by_hash {
-            if g.len() > 1 {
-                dup_groups.entry(h).insert_entry(g);
+    let library_sizes: HashSet<u64> = library_by_size.keys().copied().collect();
+    let mut library: Vec<PathBuf> = library_by_size.into_values().flatten().collect();
+    library.sort();
+    let inputs: Vec<&PathBuf> = inputs_by_size
+        .iter()
+        .filter(|(size, _)| library_sizes.contains(size))
+        .flat_map(|(_, v)| v.iter().copied())
+        .collect();
+    let mut to_hash: Vec<&Path> = library.iter().map(PathBuf::as_path).collect();
+    to_hash.extend(inputs.iter().map(|p| p.as_path()));
+    cache.start_stage("Hashing library", &to_hash);
+    let library_hashed: Vec<(PathBuf, String)> = library
+        .into_par_iter()
+        .filter_map(|p| match cache.hash(&p) {
+            Ok(h) => Some((p, h)),
+            Err(e) => {
+                eprintln!("Skipping library file {}: {e}", p.display());
+                None
             }
+        })
+        .collect();
+
+    let mut by_hash: HashMap<String, PathBuf> = HashMap::new();
+    for (p, h) in library_hashed {
+        by_hash.entry(h).or_insert(p);
+    }
+
+    let input_hashed: Vec<(&PathBuf, String)> = inputs
+        .into_par_iter()
+        .map(|p| cache.hash(p).map(|h| (p, h)))
+        .collect::<io::Result<_>>()?;
+
+    for (input, h) in input_hashed {
+        if let Some(existing) = by_hash.get(&h) {
+            matches.insert(input.clone(), existing.clone());
         }
     }
 
-    Ok(dup_groups)
+    Ok(matches)
 }
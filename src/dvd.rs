@@ -1,10 +1,28 @@
+use crate::atomic;
+use crate::cancel;
+use crate::video::EncodingProfile;
 use anyhow::{Ok, Result, ensure};
 use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-fn write_ffconcat_file(paths: &[PathBuf]) -> anyhow::Result<PathBuf> {
+/// Removes the transcoded parts however the conversion ends, including
+/// failure and cancellation.
+struct RemoveOnDrop(PathBuf);
+
+impl Drop for RemoveOnDrop {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.0).ok();
+    }
+}
+
+/// Distinguishes the work dirs of DVDs converted concurrently by one process.
+static NEXT_WORK_DIR: AtomicUsize = AtomicUsize::new(0);
+
+fn write_ffconcat_file(work_dir: &Path, paths: &[PathBuf]) -> anyhow::Result<PathBuf> {
     use std::io::Write;
 
-    let list_path = std::env::temp_dir().join(format!("concat_{}.ffconcat", std::process::id()));
+    let list_path = work_dir.join("concat.ffconcat");
     let mut f = std::fs::File::create(&list_path)?;
 
     for p in paths {
@@ -75,96 +93,125 @@ pub fn dvd_all_content_vobs(dvd_root: &Path) -> Result<Vec<PathBuf>> {
     Ok(vobs)
 }
 
-pub fn convert_dvd_vobs_to_single_mp4(dvd_root: &Path, dst_mp4: &Path) -> Result<()> {
-    if dst_mp4.exists() {
-        return Ok(());
-    }
+fn vob_to_ts_command(vob: &Path, ts_path: &Path, profile: &EncodingProfile) -> Command {
+    let mut cmd = Command::new("ffmpeg");
+    cmd.args([
+        "-y",
+        "-hide_banner",
+        "-nostats",
+        "-loglevel",
+        "warning",
+        "-fflags",
+        "+genpts+igndts+discardcorrupt",
+        "-err_detect",
+        "ignore_err",
+        "-i",
+    ])
+    .arg(vob)
+    .args(["-map", "0:v:0", "-map", "0:a?"])
+    .args(profile.codec_args())
+    .args(["-f", "mpegts"])
+    .arg(ts_path);
+    cmd
+}
+
+fn concat_command(list_path: &Path, out: &Path) -> Command {
+    let mut cmd = Command::new("ffmpeg");
+    cmd.args([
+        "-y",
+        "-hide_banner",
+        "-nostats",
+        "-loglevel",
+        "warning",
+        "-f",
+        "concat",
+        "-safe",
+        "0",
+        "-i",
+    ])
+    .arg(list_path)
+    .args([
+        "-c",
+        "copy",
+        "-bsf:a",
+        "aac_adtstoasc",
+        "-movflags",
+        "+faststart",
+    ])
+    .arg(out);
+    cmd
+}
+
+fn work_dir_path(n: usize) -> PathBuf {
+    std::env::temp_dir().join(format!("dvd_parts_{}_{n}", std::process::id()))
+}
 
+/// The ffmpeg commands converting `dvd_root` would run, for a dry run.
+pub fn dvd_conversion_commands(
+    dvd_root: &Path,
+    dst_mp4: &Path,
+    profile: &EncodingProfile,
+) -> Result<Vec<Command>> {
+    let vobs = dvd_all_content_vobs(dvd_root)?;
+    ensure!(!vobs.is_empty(), "no VOBs found for {}", dvd_root.display());
+
+    let work_dir = work_dir_path(NEXT_WORK_DIR.load(Ordering::Relaxed));
+    let mut cmds: Vec<Command> = vobs
+        .iter()
+        .enumerate()
+        .map(|(i, vob)| {
+            let ts_path = work_dir.join(format!("part-{:03}.ts", i + 1));
+            vob_to_ts_command(vob, &ts_path, profile)
+        })
+        .collect();
+    cmds.push(concat_command(
+        &work_dir.join("concat.ffconcat"),
+        &atomic::temp_path(dst_mp4),
+    ));
+    Ok(cmds)
+}
+
+/// `on_progress` is called with the number of VOBs transcoded so far and
+/// the total.
+pub fn convert_dvd_vobs_to_single_mp4(
+    dvd_root: &Path,
+    dst_mp4: &Path,
+    profile: &EncodingProfile,
+    on_progress: &dyn Fn(usize, usize),
+) -> Result<()> {
     let vobs = dvd_all_content_vobs(dvd_root)?;
     ensure!(!vobs.is_empty(), "no VOBs found for {}", dvd_root.display());
 
     // temp dir
-    let work_dir = std::env::temp_dir().join(format!("dvd_parts_{}", std::process::id()));
+    let work_dir = work_dir_path(NEXT_WORK_DIR.fetch_add(1, Ordering::Relaxed));
     std::fs::create_dir_all(&work_dir)?;
+    let _cleanup = RemoveOnDrop(work_dir.clone());
 
     let mut ts_parts: Vec<PathBuf> = Vec::new();
 
     for (i, vob) in vobs.iter().enumerate() {
+        on_progress(i, vobs.len());
         let ts_path = work_dir.join(format!("part-{:03}.ts", i + 1));
         ts_parts.push(ts_path.clone());
 
-        if ts_path.exists() {
-            continue;
-        }
-
-        let status = std::process::Command::new("ffmpeg")
-            .args([
-                "-y",
-                "-hide_banner",
-                "-nostats",
-                "-loglevel",
-                "warning",
-                "-fflags",
-                "+genpts+igndts+discardcorrupt",
-                "-err_detect",
-                "ignore_err",
-                "-i",
-                vob.to_str().unwrap(),
-                "-map",
-                "0:v:0",
-                "-map",
-                "0:a?",
-                "-c:v",
-                "libx264",
-                "-c:a",
-                "aac",
-                "-f",
-                "mpegts",
-                ts_path.to_str().unwrap(),
-            ])
-            .status()?;
+        let mut cmd = vob_to_ts_command(vob, &ts_path, profile);
+        let status = cancel::run(&mut cmd)?;
 
         ensure!(status.success(), "ffmpeg failed on VOB {}", vob.display());
     }
 
-    if let Some(parent) = dst_mp4.parent() {
-        std::fs::create_dir_all(parent)?;
-    }
+    on_progress(vobs.len(), vobs.len());
+    let list_path = write_ffconcat_file(&work_dir, &ts_parts)?;
 
-    let list_path = write_ffconcat_file(&ts_parts)?;
-
-    let status = std::process::Command::new("ffmpeg")
-        .args([
-            "-y",
-            "-hide_banner",
-            "-nostats",
-            "-loglevel",
-            "warning",
-            "-f",
-            "concat",
-            "-safe",
-            "0",
-            "-i",
-            list_path.to_str().unwrap(),
-            "-c",
-            "copy",
-            "-bsf:a",
-            "aac_adtstoasc",
-            "-movflags",
-            "+faststart",
-            dst_mp4.to_str().unwrap(),
-        ])
-        .status()?;
-
-    ensure!(
-        status.success(),
-        "ffmpeg concat failed for DVD {}",
-        dvd_root.display()
-    );
-
-    if status.success() {
-        std::fs::remove_dir_all(&work_dir).ok();
-    }
+    atomic::write_atomically(dst_mp4, |tmp| {
+        let mut cmd = concat_command(&list_path, tmp);
+        let status = cancel::run(&mut cmd)?;
 
-    Ok(())
+        ensure!(
+            status.success(),
+            "ffmpeg concat failed for DVD {}",
+            dvd_root.display()
+        );
+        Ok(())
+    })
 }
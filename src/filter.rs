@@ -0,0 +1,222 @@
+use anyhow::{Context, Result};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use walkdir::DirEntry;
+
+pub const IGNORE_FILE: &str = ".mediaignore";
+
+/// Glob lists applied while walking an input root. Patterns are matched against
+/// the path relative to the root using `/` separators; a pattern without a `/`
+/// matches at any depth and one ending in `/` only matches directories, like
+/// `.gitignore`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScanSettings {
+    /// When non-empty, only files matching one of these are planned.
+    pub include: Vec<String>,
+    /// Files and directories matching any of these are skipped entirely.
+    pub exclude: Vec<String>,
+    /// Honour `.mediaignore` files (one glob per line, relative to their directory).
+    pub mediaignore: bool,
+}
+
+impl Default for ScanSettings {
+    fn default() -> Self {
+        Self {
+            include: Vec::new(),
+            exclude: vec![
+                "@eaDir".into(),
+                ".Trash-*".into(),
+                "*.lrdata".into(),
+                ".thumbnails".into(),
+            ],
+            mediaignore: true,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ExcludedCounts {
+    pub dirs: u64,
+    pub files: u64,
+}
+
+/// Compiled patterns, split by whether they were written for directories only.
+struct Globs {
+    any: GlobSet,
+    dirs: GlobSet,
+}
+
+impl Globs {
+    fn is_match(&self, rel: &str, is_dir: bool) -> bool {
+        self.any.is_match(rel) || (is_dir && self.dirs.is_match(rel))
+    }
+}
+
+fn build_globset(patterns: &[String]) -> Result<Globs> {
+    let mut any = GlobSetBuilder::new();
+    let mut dirs = GlobSetBuilder::new();
+    for pat in patterns {
+        let pat = pat.trim().trim_start_matches('/');
+        let (pat, dir_only) = match pat.strip_suffix('/') {
+            Some(dir) => (dir, true),
+            None => (pat, false),
+        };
+        let anchored = if pat.contains('/') {
+            pat.to_string()
+        } else {
+            format!("**/{pat}")
+        };
+        let glob = GlobBuilder::new(&anchored)
+            .literal_separator(true)
+            .build()
+            .with_context(|| format!("invalid glob {pat:?}"))?;
+        if dir_only {
+            dirs.add(glob);
+        } else {
+            any.add(glob);
+        }
+    }
+    Ok(Globs {
+        any: any.build()?,
+        dirs: dirs.build()?,
+    })
+}
+
+fn relative_slash_path(path: &Path, base: &Path) -> Option<String> {
+    let rel = path.strip_prefix(base).ok()?;
+    let parts: Vec<_> = rel
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect();
+    Some(parts.join("/"))
+}
+
+pub struct ScanFilter {
+    root: PathBuf,
+    include: Option<Globs>,
+    exclude: Globs,
+    mediaignore: bool,
+    ignore_files: HashMap<PathBuf, Option<Globs>>,
+    /// `out_root` expressed under `root`, when the output lives inside the input.
+    out_root_in_input: Option<PathBuf>,
+    pub excluded: ExcludedCounts,
+}
+
+impl ScanFilter {
+    pub fn new(settings: &ScanSettings, root: &Path, out_root: &Path) -> Result<Self> {
+        let include = if settings.include.is_empty() {
+            None
+        } else {
+            Some(build_globset(&settings.include)?)
+        };
+
+        let out_root_in_input = match (root.canonicalize(), out_root.canonicalize()) {
+            (Ok(r), Ok(o)) => o.strip_prefix(&r).ok().map(|rel| root.join(rel)),
+            _ => None,
+        };
+
+        Ok(Self {
+            root: root.to_path_buf(),
+            include,
+            exclude: build_globset(&settings.exclude)?,
+            mediaignore: settings.mediaignore,
+            ignore_files: HashMap::new(),
+            out_root_in_input,
+            excluded: ExcludedCounts::default(),
+        })
+    }
+
+    fn ignore_set(&mut self, dir: &Path) -> Option<&Globs> {
+        self.ignore_files
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| {
+                let text = std::fs::read_to_string(dir.join(IGNORE_FILE)).ok()?;
+                let patterns: Vec<String> = text
+                    .lines()
+                    .map(str::trim)
+                    .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                    .map(String::from)
+                    .collect();
+                match build_globset(&patterns) {
+                    Ok(set) => Some(set),
+                    Err(e) => {
+                        eprintln!("Ignoring {}: {e:#}", dir.join(IGNORE_FILE).display());
+                        None
+                    }
+                }
+            })
+            .as_ref()
+    }
+
+    fn ignored_by_mediaignore(&mut self, path: &Path, is_dir: bool) -> bool {
+        let ancestors: Vec<PathBuf> = path
+            .ancestors()
+            .skip(1)
+            .take_while(|a| a.starts_with(&self.root))
+            .map(Path::to_path_buf)
+            .collect();
+
+        for dir in ancestors {
+            let Some(rel) = relative_slash_path(path, &dir) else {
+                continue;
+            };
+            if self
+                .ignore_set(&dir)
+                .is_some_and(|set| set.is_match(&rel, is_dir))
+            {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn excluded(&mut self, entry: &DirEntry) -> bool {
+        let path = entry.path();
+        if entry.depth() == 0 {
+            return false;
+        }
+
+        if entry.file_type().is_dir() && self.out_root_in_input.as_deref() == Some(path) {
+            return true;
+        }
+
+        let is_dir = entry.file_type().is_dir();
+        let rel = relative_slash_path(path, &self.root).unwrap_or_default();
+        if self.exclude.is_match(&rel, is_dir) {
+            return true;
+        }
+
+        if self.mediaignore && self.ignored_by_mediaignore(path, is_dir) {
+            return true;
+        }
+
+        // Include globs select files; directories are always descended into.
+        if entry.file_type().is_file()
+            && let Some(include) = &self.include
+            && !include.is_match(&rel, false)
+        {
+            return true;
+        }
+
+        false
+    }
+
+    /// Predicate for `WalkDir::filter_entry`; returning false prunes directories.
+    pub fn allows(&mut self, entry: &DirEntry) -> bool {
+        if entry.file_name() == IGNORE_FILE {
+            return false;
+        }
+        if !self.excluded(entry) {
+            return true;
+        }
+        if entry.file_type().is_dir() {
+            self.excluded.dirs += 1;
+        } else {
+            self.excluded.files += 1;
+        }
+        false
+    }
+}
@@ -0,0 +1,233 @@
+use crate::deduplicate::blake3_hash_file;
+use crate::progress::Progress;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+
+pub fn default_cache_path() -> Option<PathBuf> {
+    dirs::cache_dir().map(|d| d.join("media_organizer").join("hashes.jsonl"))
+}
+
+/// One line of the cache file. A file's hash is reused only while its size,
+/// mtime and inode are unchanged.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheRecord {
+    path: PathBuf,
+    size: u64,
+    mtime_ns: i64,
+    inode: u64,
+    hash: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileKey {
+    size: u64,
+    mtime_ns: i64,
+    inode: u64,
+}
+
+fn file_key(meta: &fs::Metadata) -> FileKey {
+    let mtime_ns = meta
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos() as i64)
+        .unwrap_or(0);
+
+    #[cfg(unix)]
+    let inode = std::os::unix::fs::MetadataExt::ino(meta);
+    #[cfg(not(unix))]
+    let inode = 0;
+
+    FileKey {
+        size: meta.len(),
+        mtime_ns,
+        inode,
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// Content hashes persisted across runs in an append-only JSONL file. Later
+/// lines supersede earlier ones; the file is compacted on save once stale
+/// lines outnumber live ones.
+pub struct HashCache {
+    path: Option<PathBuf>,
+    entries: Mutex<HashMap<PathBuf, (FileKey, String)>>,
+    pending: Mutex<Vec<CacheRecord>>,
+    lines_on_disk: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    /// Counts files looked up and bytes actually hashed.
+    progress: Option<Arc<Progress>>,
+}
+
+impl HashCache {
+    /// A cache that only lives for this run.
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            entries: Mutex::new(HashMap::new()),
+            pending: Mutex::new(Vec::new()),
+            lines_on_disk: 0,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            progress: None,
+        }
+    }
+
+    pub fn with_progress(mut self, progress: Arc<Progress>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut cache = Self::in_memory();
+        cache.path = Some(path.to_path_buf());
+
+        let file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(cache),
+            Err(e) => return Err(e).with_context(|| format!("open hash cache {}", path.display())),
+        };
+
+        let entries = cache.entries.get_mut().unwrap();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            cache.lines_on_disk += 1;
+            // A torn last line from an interrupted run is simply ignored.
+            let Ok(r) = serde_json::from_str::<CacheRecord>(&line) else {
+                continue;
+            };
+            let key = FileKey {
+                size: r.size,
+                mtime_ns: r.mtime_ns,
+                inode: r.inode,
+            };
+            entries.insert(r.path, (key, r.hash));
+        }
+
+        Ok(cache)
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Starts a progress stage for hashing `paths`: every file is counted,
+    /// but only the bytes of those the cache cannot answer for.
+    pub fn start_stage<P: AsRef<Path>>(&self, name: &'static str, paths: &[P]) {
+        let Some(progress) = &self.progress else {
+            return;
+        };
+        let entries = self.entries.lock().unwrap();
+        let bytes = paths
+            .iter()
+            .filter_map(|p| {
+                let canonical = p.as_ref().canonicalize().ok()?;
+                let key = file_key(&fs::metadata(&canonical).ok()?);
+                let cached = entries.get(&canonical).is_some_and(|(k, _)| *k == key);
+                Some(if cached { 0 } else { key.size })
+            })
+            .sum();
+        progress.stage(name, Some(paths.len() as u64), Some(bytes));
+    }
+
+    /// blake3 hex digest of `path`, from the cache when the file is unchanged.
+    pub fn hash(&self, path: &Path) -> io::Result<String> {
+        let canonical = path.canonicalize()?;
+        let key = file_key(&fs::metadata(&canonical)?);
+
+        if let Some((cached_key, hash)) = self.entries.lock().unwrap().get(&canonical)
+            && *cached_key == key
+        {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            if let Some(p) = &self.progress {
+                p.advance(1, 0);
+            }
+            return Ok(hash.clone());
+        }
+
+        let hash = blake3_hash_file(&canonical)?.to_hex().to_string();
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        if let Some(p) = &self.progress {
+            p.advance(1, key.size);
+        }
+
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(canonical.clone(), (key, hash.clone()));
+        self.pending.lock().unwrap().push(CacheRecord {
+            path: canonical,
+            size: key.size,
+            mtime_ns: key.mtime_ns,
+            inode: key.inode,
+            hash: hash.clone(),
+        });
+
+        Ok(hash)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let entries = self.entries.lock().unwrap();
+        if self.lines_on_disk + pending.len() > 2 * entries.len() + 1024 {
+            return self.compact(path, &entries);
+        }
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("open hash cache {}", path.display()))?;
+        let mut w = BufWriter::new(file);
+        for r in pending {
+            writeln!(w, "{}", serde_json::to_string(&r)?)?;
+        }
+        w.flush()?;
+        Ok(())
+    }
+
+    fn compact(&self, path: &Path, entries: &HashMap<PathBuf, (FileKey, String)>) -> Result<()> {
+        let tmp = path.with_extension("jsonl.tmp");
+        let mut w = BufWriter::new(File::create(&tmp)?);
+        // Entries for files that have since been deleted are dropped here.
+        for (p, (key, hash)) in entries.iter().filter(|(p, _)| p.exists()) {
+            let r = CacheRecord {
+                path: p.clone(),
+                size: key.size,
+                mtime_ns: key.mtime_ns,
+                inode: key.inode,
+                hash: hash.clone(),
+            };
+            writeln!(w, "{}", serde_json::to_string(&r)?)?;
+        }
+        w.flush()?;
+        fs::rename(&tmp, path).with_context(|| format!("replace hash cache {}", path.display()))?;
+        Ok(())
+    }
+}
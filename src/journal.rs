@@ -0,0 +1,296 @@
+use crate::plan::PlannedItem;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Where an item stands across apply runs. Items with no journal line yet
+/// are `Pending`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemState {
+    #[default]
+    Pending,
+    /// Started but never finished: the run was interrupted mid-item.
+    InProgress,
+    Done,
+    Failed,
+}
+
+/// A file apply wrote, with its blake3 at the time so `undo` can tell
+/// whether it has since been changed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreatedFile {
+    pub path: String,
+    pub hash: String,
+    /// Set when the file replaced different content (`--conflicts
+    /// overwrite`); undo leaves these in place.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub replaced: bool,
+}
+
+/// A `Move` source that apply removed after verifying it against
+/// `verified_against`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReleasedSource {
+    pub verified_against: String,
+    pub hash: String,
+    /// Where the source went, if it was quarantined rather than deleted.
+    pub quarantined_to: Option<String>,
+}
+
+/// What an item changed on disk, recorded so `undo` can reverse it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ItemEffects {
+    /// blake3 of the written output, when it was verified.
+    pub hash: Option<String>,
+    pub created: Vec<CreatedFile>,
+    /// Directories that did not exist before this item, deepest first.
+    pub created_dirs: Vec<String>,
+    pub released: Option<ReleasedSource>,
+    /// Where the item's content ended up, when not its planned `dst`.
+    pub written_to: Option<String>,
+}
+
+impl ItemEffects {
+    pub fn is_empty(&self) -> bool {
+        self.created.is_empty() && self.created_dirs.is_empty() && self.released.is_none()
+    }
+
+    /// Adds what a later attempt at the same item did to what earlier ones
+    /// left behind.
+    fn merge(&mut self, newer: ItemEffects) {
+        if newer.hash.is_some() {
+            self.hash = newer.hash;
+        }
+        for file in newer.created {
+            self.created.retain(|f| f.path != file.path);
+            self.created.push(file);
+        }
+        for dir in newer.created_dirs {
+            if !self.created_dirs.contains(&dir) {
+                self.created_dirs.push(dir);
+            }
+        }
+        if newer.released.is_some() {
+            self.released = newer.released;
+        }
+        if newer.written_to.is_some() {
+            self.written_to = newer.written_to;
+        }
+    }
+}
+
+/// One line of the journal. Later lines for the same `src`/`dst` supersede
+/// earlier ones.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub src: String,
+    pub dst: String,
+    pub state: ItemState,
+    #[serde(flatten)]
+    pub effects: ItemEffects,
+    pub started: Option<String>,
+    pub finished: Option<String>,
+    pub error: Option<String>,
+}
+
+/// `manifest.jsonl` -> `manifest.journal.jsonl`, so each manifest keeps its
+/// own apply history.
+pub fn default_journal_path(manifest: &Path) -> PathBuf {
+    manifest.with_extension("journal.jsonl")
+}
+
+fn key(item: &PlannedItem) -> (String, String) {
+    (item.src.clone(), item.dst.clone())
+}
+
+fn now() -> String {
+    chrono::Local::now().to_rfc3339()
+}
+
+/// Append-only JSONL record of per-item apply state, synced after every
+/// line so an interrupted run can resume exactly where it stopped.
+pub struct Journal {
+    path: PathBuf,
+    entries: HashMap<(String, String), JournalEntry>,
+    /// `None` when opened read-only.
+    file: Option<File>,
+}
+
+impl Journal {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut journal = Self::open_read_only(path)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("open journal {}", path.display()))?;
+        // End a torn last line, so the next entry is not appended to it.
+        if file.seek(SeekFrom::End(-1)).is_ok() {
+            let mut last = [0u8];
+            file.read_exact(&mut last)?;
+            if last[0] != b'\n' {
+                file.write_all(b"\n")?;
+            }
+        }
+        journal.file = Some(file);
+        Ok(journal)
+    }
+
+    /// Loads the journal without creating or appending to it, for dry runs.
+    pub fn open_read_only(path: &Path) -> Result<Self> {
+        let mut entries = HashMap::new();
+        match File::open(path) {
+            Ok(f) => {
+                for line in BufReader::new(f).lines() {
+                    // A torn last line from an interrupted run is simply ignored.
+                    let Ok(e) = serde_json::from_str::<JournalEntry>(&line?) else {
+                        continue;
+                    };
+                    entries.insert((e.src.clone(), e.dst.clone()), e);
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("open journal {}", path.display())),
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            entries,
+            file: None,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn state(&self, item: &PlannedItem) -> ItemState {
+        self.entries
+            .get(&key(item))
+            .map(|e| e.state)
+            .unwrap_or_default()
+    }
+
+    /// Where a finished item's content is, when not its planned `dst`.
+    pub fn written_to(&self, item: &PlannedItem) -> Option<&str> {
+        self.entries
+            .get(&key(item))
+            .filter(|e| e.state == ItemState::Done)
+            .and_then(|e| e.effects.written_to.as_deref())
+    }
+
+    /// blake3 of a finished item's output as apply wrote it, from the
+    /// verified copy or else from the recorded output file.
+    pub fn output_hash(&self, item: &PlannedItem) -> Option<&str> {
+        let effects = &self
+            .entries
+            .get(&key(item))
+            .filter(|e| e.state == ItemState::Done)?
+            .effects;
+        let output = effects.written_to.as_deref().unwrap_or(&item.dst);
+        effects.hash.as_deref().or_else(|| {
+            effects
+                .created
+                .iter()
+                .find(|f| f.path == output)
+                .map(|f| f.hash.as_str())
+        })
+    }
+
+    /// Latest entry per item, most recently finished first.
+    pub fn entries_newest_first(&self) -> Vec<&JournalEntry> {
+        let mut entries: Vec<&JournalEntry> = self.entries.values().collect();
+        entries.sort_by(|a, b| b.finished.cmp(&a.finished));
+        entries
+    }
+
+    fn record(&mut self, entry: JournalEntry) -> Result<()> {
+        let file = self.file.as_mut().context("journal is open read-only")?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+        file.sync_data()?;
+        self.entries
+            .insert((entry.src.clone(), entry.dst.clone()), entry);
+        Ok(())
+    }
+
+    /// What the item has changed so far: a resumed or retried item keeps
+    /// the effects of its interrupted or failed attempts.
+    pub fn effects(&self, item: &PlannedItem) -> ItemEffects {
+        self.entries
+            .get(&key(item))
+            .filter(|e| matches!(e.state, ItemState::InProgress | ItemState::Failed))
+            .map(|e| e.effects.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn start(&mut self, item: &PlannedItem) -> Result<()> {
+        let effects = self.effects(item);
+        self.record(JournalEntry {
+            src: item.src.clone(),
+            dst: item.dst.clone(),
+            state: ItemState::InProgress,
+            effects,
+            started: Some(now()),
+            finished: None,
+            error: None,
+        })
+    }
+
+    /// Records effects of an item still in progress, so they are known to
+    /// undo and to a resumed run even if the item never finishes.
+    pub fn checkpoint(&mut self, item: &PlannedItem, effects: &ItemEffects) -> Result<()> {
+        let mut entry = self
+            .entries
+            .get(&key(item))
+            .cloned()
+            .with_context(|| format!("{} was not started", item.src))?;
+        entry.effects.merge(effects.clone());
+        self.record(entry)
+    }
+
+    /// Records the outcome of an item previously passed to `start`. A failed
+    /// item keeps whatever effects it had already checkpointed.
+    pub fn finish(
+        &mut self,
+        item: &PlannedItem,
+        outcome: std::result::Result<ItemEffects, String>,
+    ) -> Result<()> {
+        let started = self.entries.get(&key(item)).and_then(|e| e.started.clone());
+        let mut effects = self.effects(item);
+        let (state, error) = match outcome {
+            Ok(newer) => {
+                effects.merge(newer);
+                (ItemState::Done, None)
+            }
+            Err(e) => (ItemState::Failed, Some(e)),
+        };
+        self.record(JournalEntry {
+            src: item.src.clone(),
+            dst: item.dst.clone(),
+            state,
+            effects,
+            started,
+            finished: Some(now()),
+            error,
+        })
+    }
+
+    /// Marks an undone item as pending, so a later apply redoes it.
+    pub fn reset(&mut self, entry: &JournalEntry) -> Result<()> {
+        self.record(JournalEntry {
+            src: entry.src.clone(),
+            dst: entry.dst.clone(),
+            state: ItemState::Pending,
+            effects: ItemEffects::default(),
+            started: None,
+            finished: Some(now()),
+            error: None,
+        })
+    }
+}
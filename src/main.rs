@@ -1,34 +1,81 @@
-use anyhow::{Ok, Result};
-use std::fs::File;
-use std::io::Write;
-use std::path::PathBuf;
+use anyhow::Result;
+use clap::{CommandFactory, Parser};
+use cli::{Cli, Command};
+use preflight::SpaceCheck;
+use progress::Progress;
+use std::process::ExitCode;
+use std::sync::Arc;
 
 mod apply;
+mod apply_log;
+mod atomic;
+mod cancel;
 mod classify;
+mod cli;
+mod config;
 mod deduplicate;
 mod dvd;
+mod filter;
+mod hash_cache;
+mod journal;
 mod manifest;
+mod perceptual;
 mod photo;
 mod plan;
+mod preflight;
+mod progress;
+mod rank;
 mod report;
 mod time;
+mod undo;
 mod video;
+mod workers;
+mod xmp;
 
-fn main() -> Result<()> {
-    let mut args = std::env::args().skip(1);
-    let cmd = args.next().unwrap_or_else(|| "help".to_string());
+fn main() -> Result<ExitCode> {
+    let cli = Cli::parse();
+    let mut config = config::load(cli.config.as_deref())?;
 
-    match cmd.as_str() {
-        "plan" => {
-            let root = PathBuf::from(args.next().unwrap_or_else(|| ".".to_string()));
-            let out_root = PathBuf::from(args.next().unwrap_or_else(|| "./ExportSet".to_string()));
+    match cli.command {
+        Command::Plan(args) => {
+            let progress = Arc::new(Progress::new(!args.no_progress));
+            args.override_config(&mut config);
+            let input_roots = config.input_roots();
+            let out_root = config.out_root();
+            let manifest_path = config.manifest();
 
-            let (items, summary) = plan::build_plan(&root, &out_root)?;
+            // Record resolved values so the header shows exactly what ran.
+            config.input_roots = input_roots.clone();
+            config.out_root = Some(out_root.clone());
+            config.manifest = Some(manifest_path.clone());
 
-            let mut f = File::create("manifest.jsonl")?;
-            for item in items {
-                writeln!(f, "{}", serde_json::to_string(&item)?)?;
-            }
+            let options = plan::PlanOptions {
+                naming: config.naming,
+                extensions: config.extensions.clone(),
+                layout: config.layout.clone(),
+                detect_duplicates: config.duplicates.detect,
+                check_library: config.duplicates.against_library,
+                near_duplicate_threshold: config
+                    .duplicates
+                    .near_photos
+                    .then_some(config.duplicates.near_threshold),
+                near_video_threshold: config
+                    .duplicates
+                    .near_videos
+                    .then_some(config.duplicates.near_video_threshold),
+                rank: config.duplicates.rank.clone(),
+                hash_all: config.duplicates.hash_all,
+                move_sources: config.move_sources,
+                scan: config.scan.clone(),
+                hash_cache: config.hash_cache.effective_path(),
+                threads: config.parallel.threads,
+                ffprobe_processes: config.parallel.ffprobe_processes,
+            };
+
+            let (items, summary) = plan::build_plan(&input_roots, &out_root, &options, &progress)?;
+
+            let header = manifest::ManifestHeader::new(&config)?;
+            manifest::write_manifest_jsonl(&manifest_path, header, &items)?;
 
             println!("Planned items:       {}", summary.planned);
             println!("Photos:              {}", summary.photos);
@@ -39,45 +86,245 @@ fn main() -> Result<()> {
             println!("Need convert (dvd):  {}", summary.need_convert_dvd);
             println!("Duplicate photos:    {}", summary.duplicate_photos);
             println!("Duplicate videos:    {}", summary.duplicate_videos);
+            println!("Near-dup photos:     {}", summary.near_duplicate_photos);
+            println!("Near-dup videos:     {}", summary.near_duplicate_videos);
+            println!("Already in library:  {}", summary.already_in_library);
+            println!(
+                "Hash cache:          {} hits, {} misses",
+                summary.hash_cache_hits, summary.hash_cache_misses
+            );
+            println!("Excluded dirs:       {}", summary.excluded_dirs);
+            println!("Excluded files:      {}", summary.excluded_files);
             println!("Out root:            {}", out_root.display());
-            println!("Wrote:               manifest.jsonl");
+            println!("Wrote:               {}", manifest_path.display());
+
+            if summary.by_root.len() > 1 {
+                println!(
+                    "\nBy input root:   planned  photos  videos    dvds    dups  in-lib  excluded"
+                );
+                for (label, r) in &summary.by_root {
+                    println!(
+                        "  {label:14} {:>7} {:>7} {:>7} {:>7} {:>7} {:>7} {:>9}",
+                        r.planned,
+                        r.photos,
+                        r.videos,
+                        r.dvds,
+                        r.duplicates,
+                        r.already_in_library,
+                        r.excluded_dirs + r.excluded_files
+                    );
+                }
+            }
         }
-        "apply" => {
-            let manifest =
-                PathBuf::from(args.next().unwrap_or_else(|| "manifest.jsonl".to_string()));
-            let items = manifest::read_manifest_jsonl(&manifest)?;
-            let summary = apply::apply_items(&items)?;
+        Command::Apply(args) => {
+            let retry_failed = args.retry_failed;
+            let dry_run = args.dry_run;
+            // Progress would only get in the way of the per-item lines.
+            let progress = Progress::new(!args.no_progress && !dry_run);
+            args.override_config(&mut config);
+            let manifest_path = config.manifest();
+            let manifest = manifest::read_manifest_jsonl(&manifest_path)?;
+            let journal_path = config
+                .apply
+                .journal
+                .clone()
+                .unwrap_or_else(|| journal::default_journal_path(&manifest_path));
+            let mut journal = if dry_run {
+                journal::Journal::open_read_only(&journal_path)?
+            } else {
+                journal::Journal::open(&journal_path)?
+            };
+
+            if config.apply.space_check != SpaceCheck::Off {
+                let out_root = manifest
+                    .header
+                    .as_ref()
+                    .and_then(|h| h.out_root())
+                    .unwrap_or_else(|| config.out_root());
+                let pre = preflight::check(
+                    &manifest.items,
+                    &out_root,
+                    &journal,
+                    config.apply.estimated_video_kbps,
+                    retry_failed,
+                    dry_run,
+                );
+                println!("Pre-flight out root:  {}", out_root.display());
+                println!(
+                    "Copies:               {} ({})",
+                    pre.copies,
+                    preflight::human_bytes(pre.copy_bytes)
+                );
+                println!(
+                    "Conversions:          {} (~{})",
+                    pre.conversions,
+                    preflight::human_bytes(pre.conversion_bytes)
+                );
+                if pre.unknown_size > 0 {
+                    println!("Unknown size:         {}", pre.unknown_size);
+                }
+                match pre.free_bytes {
+                    Some(free) => {
+                        println!("Free space:           {}", preflight::human_bytes(free))
+                    }
+                    None => println!("Free space:           unknown"),
+                }
+                let problems = pre.problems();
+                for problem in &problems {
+                    eprintln!("Pre-flight: {problem}");
+                }
+                if !problems.is_empty() && config.apply.space_check == SpaceCheck::Fail {
+                    if !dry_run {
+                        anyhow::bail!(
+                            "pre-flight check failed; rerun with --space-check warn to apply anyway"
+                        );
+                    }
+                    eprintln!("Pre-flight: a real run would stop here");
+                }
+            }
+
+            let options = apply::ApplyOptions {
+                xmp_sidecars: config.xmp_sidecars,
+                log_dir: config.log_dir(),
+                run_id: apply_log::new_run_id(),
+                video_encoding: config.encoding.video.clone(),
+                dvd_encoding: config.encoding.dvd.clone(),
+                duplicates: config.duplicates.policy,
+                quarantine: config.apply.quarantine.clone(),
+                verify_copies: config.apply.verify_copies,
+                conflicts: config.apply.conflicts,
+                retry_failed,
+                copy_jobs: config.parallel.copy_jobs,
+                ffmpeg_jobs: config.parallel.ffmpeg_jobs,
+                dry_run,
+            };
+            if dry_run {
+                println!("\nDry run: nothing will be written, moved or encoded.");
+            }
+            cancel::install_handler()?;
+            let summary = apply::apply_items(&manifest.items, &options, &mut journal, &progress)?;
 
-            println!("Applied manifest:     {}", manifest.display());
+            if dry_run {
+                println!("\nDry-run manifest:     {}", manifest_path.display());
+            } else {
+                println!("Applied manifest:     {}", manifest_path.display());
+            }
             println!("Total:                {}", summary.total);
             println!("Copied:               {}", summary.copied);
             println!("Converted videos:     {}", summary.converted_video);
             println!("Converted DVDs:       {}", summary.converted_dvd);
             println!("Skipped existing:     {}", summary.skipped_existing);
+            println!("Conflicts renamed:    {}", summary.conflicts_renamed);
+            if summary.conflicts_overwritten > 0 {
+                println!("Conflicts replaced:   {}", summary.conflicts_overwritten);
+            }
             println!("Skipped duplicate:    {}", summary.skipped_dupliace);
+            println!("Linked duplicate:     {}", summary.linked_duplicates);
+            println!("Skipped in library:   {}", summary.skipped_in_library);
+            println!("Sources moved:        {}", summary.moved);
+            println!("Stale temps removed:  {}", summary.stale_temps_removed);
+            println!("Skipped (journal):    {}", summary.skipped_journal);
+            println!("Resumed:              {}", summary.resumed);
             println!("Failed:               {}", summary.failed);
-            println!("Logs: apply_ok.log, apply_fail.log, apply_duplicates_skipped.log");
+            if summary.cancelled > 0 {
+                println!("Cancelled:            {}", summary.cancelled);
+            }
+            if summary.previously_failed > 0 {
+                println!(
+                    "Failed earlier:       {} (rerun with --retry-failed)",
+                    summary.previously_failed
+                );
+            }
+            if !dry_run {
+                println!("Journal:              {}", journal.path().display());
+                println!(
+                    "Log:                  {}",
+                    apply_log::log_path(&options.log_dir, &options.run_id).display()
+                );
+            }
+
+            if cancel::requested() {
+                eprintln!("Apply was cancelled; run it again to resume.");
+                return Ok(ExitCode::from(130));
+            }
+            if summary.failed > 0 {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Command::Report(args) => {
+            let manifest_path = args.manifest.unwrap_or_else(|| config.manifest());
+            let manifest = manifest::read_manifest_jsonl(&manifest_path)?;
+            let journal = if args.validate_outputs {
+                let journal_path = config
+                    .apply
+                    .journal
+                    .clone()
+                    .unwrap_or_else(|| journal::default_journal_path(&manifest_path));
+                Some(journal::Journal::open_read_only(&journal_path)?)
+            } else {
+                None
+            };
+            let (summary, notes) =
+                report::build_report(&manifest.items, args.validate_outputs, journal.as_ref())?;
+            report::print_report(&summary, &notes, args.validate_outputs);
+            if let Some(path) = &args.apply_log {
+                let path = if path.is_dir() {
+                    apply_log::latest_log(path)?
+                } else {
+                    path.clone()
+                };
+                let events = apply_log::read_events(&path)?;
+                report::print_apply_log(&path, &events);
+            }
+
+            println!("\nManifest: {}", manifest_path.display());
+            if let Some(header) = &manifest.header {
+                println!("Planned:  {} (v{})", header.created, header.tool_version);
+                if let Some(out_root) = header.out_root() {
+                    println!("Out root: {}", out_root.display());
+                }
+            }
+            if !args.validate_outputs {
+                println!(
+                    "Tip: run `media_organizer report --manifest {} --validate-outputs` after apply.",
+                    manifest_path.display()
+                );
+            }
+            if summary.outputs_missing + summary.outputs_hash_mismatch > 0 {
+                return Ok(ExitCode::FAILURE);
+            }
         }
-        "report" => {
-            let manifest =
-                PathBuf::from(args.next().unwrap_or_else(|| "manifest.jsonl".to_string()));
-            let validate_outputs = args.next().as_deref() == Some("--validate-outputs");
+        Command::Undo { journal } => {
+            let mut journal = journal::Journal::open(&journal)?;
+            let summary = undo::undo(&mut journal)?;
 
-            let items = manifest::read_manifest_jsonl(&manifest)?;
-            let (summary, notes) = report::build_report(&items, validate_outputs)?;
-            report::print_report(&summary, &notes, validate_outputs);
+            println!("Undone items:         {}", summary.undone);
+            println!("Outputs removed:      {}", summary.removed);
+            println!("Sources restored:     {}", summary.sources_restored);
+            println!("Empty dirs pruned:    {}", summary.dirs_pruned);
+            for path in &summary.kept_modified {
+                println!("Kept (changed since apply): {path}");
+            }
+            for path in &summary.kept_replaced {
+                eprintln!("Kept (replaced an existing file, which is gone): {path}");
+            }
+            for failure in &summary.failures {
+                eprintln!("Failed: {failure}");
+            }
 
-            println!("\nManifest: {}", manifest.display());
-            println!(
-                "Tip: run `cargo run -- report manifest.jsonl --validate-outputs` after apply."
-            );
+            if !summary.failures.is_empty() {
+                return Ok(ExitCode::FAILURE);
+            }
         }
-        _ => {
-            eprintln!("Usage:");
-            eprintln!(" cargo run -- plan <input_root> <out_root>");
-            eprintln!(" cargo run -- apply [manifest.jsonl]");
+        Command::Completions { shell } => {
+            clap_complete::generate(
+                shell,
+                &mut Cli::command(),
+                "media_organizer",
+                &mut std::io::stdout(),
+            );
         }
     }
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
@@ -1,23 +1,87 @@
+use crate::config::Config;
 use crate::plan::PlannedItem;
 use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::path::Path;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
 
-pub fn read_manifest_jsonl(path: &Path) -> Result<Vec<PlannedItem>> {
+/// First line of a manifest, recording how it was produced. Older manifests
+/// have no header. The config is kept as raw JSON so manifests written by
+/// other versions still load.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManifestHeader {
+    pub tool_version: String,
+    pub created: String,
+    pub config: JsonValue,
+}
+
+impl ManifestHeader {
+    pub fn new(config: &Config) -> Result<Self> {
+        Ok(Self {
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            created: chrono::Local::now().to_rfc3339(),
+            config: serde_json::to_value(config)?,
+        })
+    }
+
+    pub fn out_root(&self) -> Option<PathBuf> {
+        self.config
+            .get("out_root")
+            .and_then(|v| v.as_str())
+            .map(PathBuf::from)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct HeaderLine {
+    header: ManifestHeader,
+}
+
+pub struct Manifest {
+    pub header: Option<ManifestHeader>,
+    pub items: Vec<PlannedItem>,
+}
+
+pub fn write_manifest_jsonl(
+    path: &Path,
+    header: ManifestHeader,
+    items: &[PlannedItem],
+) -> Result<()> {
+    let file = File::create(path).with_context(|| format!("create manifest {}", path.display()))?;
+    let mut w = BufWriter::new(file);
+
+    writeln!(w, "{}", serde_json::to_string(&HeaderLine { header })?)?;
+    for item in items {
+        writeln!(w, "{}", serde_json::to_string(item)?)?;
+    }
+    w.flush()?;
+
+    Ok(())
+}
+
+pub fn read_manifest_jsonl(path: &Path) -> Result<Manifest> {
     let file = File::open(path).with_context(|| format!("open manifest {}", path.display()))?;
     let reader = BufReader::new(file);
 
+    let mut header = None;
     let mut items = Vec::new();
     for (i, line) in reader.lines().enumerate() {
         let line = line?;
         if line.trim().is_empty() {
             continue;
         }
+        if i == 0 && line.starts_with("{\"header\":") {
+            let parsed: HeaderLine =
+                serde_json::from_str(&line).context("parse manifest header")?;
+            header = Some(parsed.header);
+            continue;
+        }
         let item: PlannedItem =
             serde_json::from_str(&line).with_context(|| format!("parse json on line {}", i + 1))?;
         items.push(item);
     }
 
-    Ok(items)
+    Ok(Manifest { header, items })
 }
@@ -0,0 +1,132 @@
+use crate::video;
+use image::DynamicImage;
+use image::imageops::FilterType;
+use std::path::Path;
+
+/// dHash over a 9x8 greyscale grid, row-major.
+fn dhash_grid(luma: &[u8]) -> u64 {
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let left = luma[y * 9 + x];
+            let right = luma[y * 9 + x + 1];
+            hash = (hash << 1) | u64::from(left > right);
+        }
+    }
+    hash
+}
+
+/// 64-bit difference hash: shrink to 9x8 greyscale and record whether each
+/// pixel is brighter than its right-hand neighbour. Survives resizing,
+/// recompression and metadata stripping, which is what re-shared photos go
+/// through.
+pub fn dhash(img: &DynamicImage) -> u64 {
+    let small = img.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    dhash_grid(small.as_raw())
+}
+
+pub struct PhotoFingerprint {
+    pub dhash: u64,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Decodes a photo and fingerprints it. Undecodable files yield `None` rather
+/// than failing the plan.
+pub fn photo_fingerprint(path: &Path) -> Option<PhotoFingerprint> {
+    let img = image::open(path).ok()?;
+    Some(PhotoFingerprint {
+        dhash: dhash(&img),
+        width: img.width(),
+        height: img.height(),
+    })
+}
+
+/// Frames sampled per video, spread evenly and skipping the very start and
+/// end where fades and black frames make every clip look alike.
+const VIDEO_SAMPLE_FRAMES: usize = 5;
+
+/// Clips whose durations differ by more than this fraction (or one second,
+/// whichever is larger) are never compared.
+const VIDEO_DURATION_TOLERANCE: f64 = 0.02;
+
+pub struct VideoFingerprint {
+    pub duration_secs: f64,
+    pub frames: Vec<u64>,
+}
+
+/// Samples frames through ffmpeg and dHashes each. Needs the duration from
+/// ffprobe; clips that are too short or fail to decode yield `None`.
+pub fn video_fingerprint(path: &Path, duration_secs: f64) -> Option<VideoFingerprint> {
+    if duration_secs < 1.0 {
+        return None;
+    }
+    let step = duration_secs / (VIDEO_SAMPLE_FRAMES + 1) as f64;
+    let frames = (1..=VIDEO_SAMPLE_FRAMES)
+        .map(|k| video::ffmpeg_grey_frame(path, step * k as f64, 9, 8).map(|f| dhash_grid(&f)))
+        .collect::<Option<Vec<u64>>>()?;
+
+    Some(VideoFingerprint {
+        duration_secs,
+        frames,
+    })
+}
+
+/// Mean differing bits per sampled frame, or `None` when the durations are
+/// too far apart for the clips to be the same footage.
+pub fn video_distance(a: &VideoFingerprint, b: &VideoFingerprint) -> Option<u32> {
+    let tolerance = (a.duration_secs.max(b.duration_secs) * VIDEO_DURATION_TOLERANCE).max(1.0);
+    if (a.duration_secs - b.duration_secs).abs() > tolerance {
+        return None;
+    }
+    let total: u32 = a
+        .frames
+        .iter()
+        .zip(&b.frames)
+        .map(|(&x, &y)| hamming(x, y))
+        .sum();
+    Some(total / a.frames.len() as u32)
+}
+
+pub fn hamming(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Fraction of matching bits, 1.0 for identical hashes.
+pub fn similarity(distance: u32, bits: u32) -> f32 {
+    1.0 - distance as f32 / bits as f32
+}
+
+/// Greedy clustering of fingerprints already sorted best-first: each entry
+/// joins the closest earlier canonical within `threshold` bits, otherwise it
+/// becomes a canonical itself. Returns `(index, canonical index, distance)`
+/// for every entry that joined a group.
+pub fn cluster_by_hamming(hashes: &[u64], threshold: u32) -> Vec<(usize, usize, u32)> {
+    cluster(hashes, threshold, |a, b| Some(hamming(*a, *b)))
+}
+
+/// `cluster_by_hamming` for any fingerprint; `distance` returns `None` for
+/// pairs that can never match.
+pub fn cluster<T>(
+    items: &[T],
+    threshold: u32,
+    distance: impl Fn(&T, &T) -> Option<u32>,
+) -> Vec<(usize, usize, u32)> {
+    let mut canonicals: Vec<usize> = Vec::new();
+    let mut matches = Vec::new();
+
+    for (i, item) in items.iter().enumerate() {
+        let best = canonicals
+            .iter()
+            .filter_map(|&c| distance(&items[c], item).map(|d| (c, d)))
+            .filter(|&(_, d)| d <= threshold)
+            .min_by_key(|&(c, d)| (d, c));
+
+        match best {
+            Some((c, d)) => matches.push((i, c, d)),
+            None => canonicals.push(i),
+        }
+    }
+
+    matches
+}
@@ -1,8 +1,28 @@
 use anyhow::Result;
-use chrono::NaiveDateTime;
+use chrono::{NaiveDateTime, Timelike};
 use exif::{In, Reader, Tag, Value};
 use std::{fs::File, io::BufReader, path::Path};
 
+fn parse_exif_subsec(value: &Value) -> Option<u32> {
+    let s = match value {
+        Value::Ascii(vec) if !vec.is_empty() => String::from_utf8_lossy(&vec[0]).to_string(),
+        _ => return None,
+    };
+
+    let digits: String = s
+        .trim()
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    if digits.is_empty() {
+        return None;
+    }
+
+    // SubSecTime is a decimal fraction ("12" means 0.12s), so pad to nanoseconds.
+    let nanos = format!("{:0<9}", &digits[..digits.len().min(9)]);
+    nanos.parse().ok()
+}
+
 fn parse_exif_datetime(value: &Value) -> Option<NaiveDateTime> {
     let s = match value {
         Value::Ascii(vec) if !vec.is_empty() => String::from_utf8_lossy(&vec[0]).to_string(),
@@ -13,26 +33,37 @@ fn parse_exif_datetime(value: &Value) -> Option<NaiveDateTime> {
     NaiveDateTime::parse_from_str(s, "%Y:%m:%d %H:%M:%S").ok()
 }
 
-pub fn exif_capture_datetime(path: &Path) -> Result<Option<NaiveDateTime>> {
+/// What the planner needs from a photo's EXIF block.
+pub struct ExifInfo {
+    pub capture: Option<NaiveDateTime>,
+}
+
+/// Returns `None` when the file has no readable EXIF at all, which for a JPEG
+/// usually means it was re-saved by a messenger or editor.
+pub fn read_exif(path: &Path) -> Result<Option<ExifInfo>> {
     let file = File::open(path)?;
     let mut reader = BufReader::new(file);
 
-    let exif_data = Reader::new().read_from_container(&mut reader).ok();
-
-    if let Some(exif) = exif_data {
-        if let Some(dt) = exif
-            .get_field(Tag::DateTimeOriginal, In::PRIMARY)
-            .and_then(|f| parse_exif_datetime(&f.value))
-        {
-            return Ok(Some(dt));
-        }
+    let Some(exif) = Reader::new().read_from_container(&mut reader).ok() else {
+        return Ok(None);
+    };
 
+    for (dt_tag, subsec_tag) in [
+        (Tag::DateTimeOriginal, Tag::SubSecTimeOriginal),
+        (Tag::DateTime, Tag::SubSecTime),
+    ] {
         if let Some(dt) = exif
-            .get_field(Tag::DateTime, In::PRIMARY)
+            .get_field(dt_tag, In::PRIMARY)
             .and_then(|f| parse_exif_datetime(&f.value))
         {
-            return Ok(Some(dt));
+            let subsec = exif
+                .get_field(subsec_tag, In::PRIMARY)
+                .and_then(|f| parse_exif_subsec(&f.value));
+            return Ok(Some(ExifInfo {
+                capture: Some(subsec.and_then(|ns| dt.with_nanosecond(ns)).unwrap_or(dt)),
+            }));
         }
     }
-    Ok(None)
+
+    Ok(Some(ExifInfo { capture: None }))
 }
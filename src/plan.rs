@@ -1,16 +1,29 @@
-use crate::classify::{Kind, classify, normalize_extension};
-use crate::time::{DateSource, best_datetime_for_dvd, best_datetime_for_file, format_dt};
-use crate::{deduplicate, dvd};
+use crate::classify::is_jpeg;
+use crate::classify::{ExtensionMap, Kind, classify, normalize_extension};
+use crate::filter::{ScanFilter, ScanSettings};
+use crate::hash_cache::HashCache;
+use crate::progress::Progress;
+use crate::rank::{RankPolicy, Ranker};
+use crate::time::{
+    DateSource, best_datetime_for_dvd, best_datetime_for_photo, best_datetime_for_video, format_dt,
+};
+use crate::workers::{self, Semaphore};
+use crate::{deduplicate, dvd, perceptual, photo, video};
 use anyhow::Result;
-use chrono::NaiveDateTime;
+use chrono::{NaiveDateTime, Timelike};
+use clap::ValueEnum;
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use walkdir::WalkDir;
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum Action {
     Copy,
+    /// Copy, verify, then remove the source.
+    Move,
     ConvertVideo,
     ConvertDvd,
 }
@@ -22,6 +35,192 @@ pub enum MediaKind {
     Dvd,
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum NamingMode {
+    /// Keep the source file stem (`DSC_0042.jpg`).
+    #[default]
+    Original,
+    /// Name outputs after the capture time (`2019-03-12_14-15-22.jpg`).
+    Timestamp,
+}
+
+/// Directory layout under `out_root`. Templates may use `{kind}` (the per-kind
+/// directory name), `{year}`, `{month}` and `{day}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LayoutTemplates {
+    pub dated: String,
+    pub undated: String,
+    pub photo_dir: String,
+    pub video_dir: String,
+    pub dvd_dir: String,
+}
+
+impl Default for LayoutTemplates {
+    fn default() -> Self {
+        Self {
+            dated: "{kind}/{year}/{year}-{month}/{year}-{month}-{day}".into(),
+            undated: "{kind}/UnknownDate".into(),
+            photo_dir: "Photos".into(),
+            video_dir: "Videos".into(),
+            dvd_dir: "DVDs".into(),
+        }
+    }
+}
+
+impl LayoutTemplates {
+    fn render_dir(&self, kind: MediaKind, best_dt: Option<NaiveDateTime>) -> PathBuf {
+        let kind_dir = match kind {
+            MediaKind::Photo => &self.photo_dir,
+            MediaKind::Video => &self.video_dir,
+            MediaKind::Dvd => &self.dvd_dir,
+        };
+
+        let rendered = match best_dt {
+            Some(dt) => self
+                .dated
+                .replace("{year}", &dt.format("%Y").to_string())
+                .replace("{month}", &dt.format("%m").to_string())
+                .replace("{day}", &dt.format("%d").to_string()),
+            None => self.undated.clone(),
+        };
+
+        PathBuf::from(rendered.replace("{kind}", kind_dir))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PlanOptions {
+    pub naming: NamingMode,
+    pub extensions: ExtensionMap,
+    pub layout: LayoutTemplates,
+    pub detect_duplicates: bool,
+    /// Skip inputs whose content already exists under `out_root`.
+    pub check_library: bool,
+    /// Hamming threshold for perceptual photo matching; `None` disables it.
+    pub near_duplicate_threshold: Option<u32>,
+    /// Mean per-frame Hamming threshold for video matching; `None` disables it.
+    pub near_video_threshold: Option<u32>,
+    /// How the copy to keep is chosen within a duplicate group.
+    pub rank: RankPolicy,
+    /// Fill in `content_hash` for every photo and video.
+    pub hash_all: bool,
+    /// Plan copies as moves, so apply removes sources once written.
+    pub move_sources: bool,
+    pub scan: ScanSettings,
+    /// Persistent hash cache file; `None` hashes everything afresh.
+    pub hash_cache: Option<PathBuf>,
+    /// Worker threads for metadata reads and hashing; 0 means one per CPU.
+    pub threads: usize,
+    /// Maximum ffprobe/ffmpeg processes running at once while planning.
+    pub ffprobe_processes: usize,
+}
+
+impl Default for PlanOptions {
+    fn default() -> Self {
+        Self {
+            naming: NamingMode::default(),
+            extensions: ExtensionMap::default(),
+            layout: LayoutTemplates::default(),
+            detect_duplicates: true,
+            check_library: true,
+            near_duplicate_threshold: None,
+            near_video_threshold: None,
+            rank: RankPolicy::default(),
+            hash_all: false,
+            move_sources: false,
+            scan: ScanSettings::default(),
+            hash_cache: None,
+            threads: 0,
+            ffprobe_processes: 4,
+        }
+    }
+}
+
+/// An input tree and the label its items are tagged with, written on the
+/// command line or in config as `LABEL=PATH` or just `PATH` (labelled by the
+/// directory name).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct InputRoot {
+    pub label: String,
+    pub path: PathBuf,
+}
+
+impl TryFrom<String> for InputRoot {
+    type Error = String;
+
+    fn try_from(s: String) -> std::result::Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<InputRoot> for String {
+    fn from(root: InputRoot) -> Self {
+        format!("{}={}", root.label, root.path.display())
+    }
+}
+
+impl std::str::FromStr for InputRoot {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err("empty input root".into());
+        }
+
+        if let Some((label, path)) = s.split_once('=')
+            && !label.is_empty()
+            && !label.contains(['/', '\\'])
+        {
+            return Ok(Self {
+                label: label.to_string(),
+                path: PathBuf::from(path),
+            });
+        }
+
+        let path = PathBuf::from(s);
+        let label = path
+            .canonicalize()
+            .unwrap_or_else(|_| path.clone())
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| s.to_string());
+        Ok(Self { label, path })
+    }
+}
+
+fn check_input_roots(roots: &[InputRoot]) -> Result<()> {
+    anyhow::ensure!(!roots.is_empty(), "no input roots given");
+
+    let mut labels = HashSet::new();
+    for r in roots {
+        anyhow::ensure!(
+            labels.insert(&r.label),
+            "duplicate input root label {:?}; use LABEL=PATH to name roots",
+            r.label
+        );
+    }
+
+    let canon: Vec<PathBuf> = roots
+        .iter()
+        .map(|r| r.path.canonicalize().unwrap_or_else(|_| r.path.clone()))
+        .collect();
+    for (i, a) in canon.iter().enumerate() {
+        for (j, b) in canon.iter().enumerate() {
+            anyhow::ensure!(
+                i == j || !a.starts_with(b),
+                "input root {} is inside input root {}",
+                roots[i].path.display(),
+                roots[j].path.display()
+            );
+        }
+    }
+
+    Ok(())
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PlannedItem {
     pub kind: MediaKind,
@@ -33,6 +232,35 @@ pub struct PlannedItem {
     pub size_bytes: Option<u64>,
     pub content_hash: Option<String>,
     pub duplicate_of: Option<String>,
+    pub original_name: Option<String>,
+    /// Label of the input root this item was found under.
+    pub source_root: Option<String>,
+    /// Visually the same as this item, though not byte-identical. Still applied.
+    pub near_duplicate_of: Option<String>,
+    /// Perceptual similarity to `near_duplicate_of`, from 0.0 to 1.0.
+    pub similarity: Option<f32>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Video length from ffprobe.
+    pub duration_secs: Option<f64>,
+    /// Whether the file looks re-saved (EXIF stripped, or written by ffmpeg).
+    pub reencoded: Option<bool>,
+    /// Set on the kept copy of a duplicate group: the rule that picked it.
+    pub canonical_reason: Option<String>,
+    /// Existing file under `out_root` with identical content; apply skips these.
+    pub already_in_library: Option<String>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct RootSummary {
+    pub planned: u64,
+    pub photos: u64,
+    pub videos: u64,
+    pub dvds: u64,
+    pub duplicates: u64,
+    pub already_in_library: u64,
+    pub excluded_dirs: u64,
+    pub excluded_files: u64,
 }
 
 #[derive(Debug)]
@@ -46,6 +274,15 @@ pub struct PlanSummary {
     pub need_convert_dvd: u64,
     pub duplicate_photos: u64,
     pub duplicate_videos: u64,
+    pub near_duplicate_photos: u64,
+    pub near_duplicate_videos: u64,
+    pub already_in_library: u64,
+    pub excluded_dirs: u64,
+    pub excluded_files: u64,
+    pub hash_cache_hits: u64,
+    pub hash_cache_misses: u64,
+    /// Per input root, in the order the roots were given.
+    pub by_root: Vec<(String, RootSummary)>,
 }
 
 impl PlanSummary {
@@ -60,6 +297,14 @@ impl PlanSummary {
             need_convert_dvd: 0,
             duplicate_photos: 0,
             duplicate_videos: 0,
+            near_duplicate_photos: 0,
+            near_duplicate_videos: 0,
+            already_in_library: 0,
+            excluded_dirs: 0,
+            excluded_files: 0,
+            hash_cache_hits: 0,
+            hash_cache_misses: 0,
+            by_root: Vec::new(),
         }
     }
 }
@@ -71,90 +316,273 @@ fn safe_stem(path: &Path) -> String {
         .to_string()
 }
 
+fn file_name_string(path: &Path) -> Option<String> {
+    path.file_name().map(|n| n.to_string_lossy().to_string())
+}
+
 fn plan_dst(
     out_root: &Path,
     kind: MediaKind,
     src: &Path,
     best_dt: Option<NaiveDateTime>,
+    options: &PlanOptions,
 ) -> PathBuf {
-    let base = match kind {
-        MediaKind::Photo => out_root.join("Photos"),
-        MediaKind::Video => out_root.join("Videos"),
-        MediaKind::Dvd => out_root.join("DVDs"),
-    };
-
-    let (year, ym, ymd) = if let Some(dt) = best_dt {
-        let d = dt.date();
-        (
-            d.format("%Y").to_string(),
-            d.format("%Y-%m").to_string(),
-            d.format("%Y-%m-%d").to_string(),
-        )
-    } else {
-        (
-            "UnknownDate".into(),
-            "UnknownDate".into(),
-            "UnknownDate".into(),
-        )
-    };
-
-    let dir = if year == "UnknownDate" {
-        base.join("UnknownDate")
-    } else {
-        base.join(year).join(ym).join(ymd)
-    };
+    let dir = out_root.join(options.layout.render_dir(kind, best_dt));
 
     let ext = match kind {
         MediaKind::Photo => normalize_extension(src).unwrap_or_else(|| "jpg".into()),
         MediaKind::Video | MediaKind::Dvd => "mp4".into(),
     };
 
-    let name = match kind {
-        MediaKind::Dvd => src
+    let name = match (kind, options.naming, best_dt) {
+        (MediaKind::Dvd, _, _) => src
             .file_name()
             .and_then(|n| n.to_str())
             .unwrap_or("DVD")
             .to_string(),
+        (_, NamingMode::Timestamp, Some(dt)) => dt.format("%Y-%m-%d_%H-%M-%S").to_string(),
         _ => safe_stem(src),
     };
 
     dir.join(format!("{name}.{ext}"))
 }
 
-fn action_for_video(path: &Path) -> Action {
-    match normalize_extension(path).as_deref() {
-        Some("avi") => Action::ConvertVideo,
-        _ => Action::Copy,
+/// Marks copy-only inputs that already exist, byte for byte, somewhere in the
+/// output library, so re-importing a card does not duplicate it under new names.
+/// Conversions are left alone since their outputs never match the source.
+fn mark_already_in_library(
+    planned: &mut [PlannedItem],
+    summary: &mut PlanSummary,
+    out_root: &Path,
+    cache: &HashCache,
+) -> Result<()> {
+    let paths: Vec<PathBuf> = planned
+        .iter()
+        .filter(|i| matches!(i.action, Action::Copy | Action::Move) && i.duplicate_of.is_none())
+        .map(|i| PathBuf::from(&i.src))
+        .collect();
+
+    let found = deduplicate::find_library_matches(&paths, out_root, cache)?;
+    for item in planned.iter_mut() {
+        if let Some(existing) = found.get(Path::new(&item.src)) {
+            item.already_in_library = Some(existing.to_string_lossy().to_string());
+            summary.already_in_library += 1;
+        }
+    }
+
+    Ok(())
+}
+
+/// Groups photos that look the same after resizing or recompression. Exact
+/// duplicates are already handled, so only their canonicals take part. Within
+/// a group the best-ranked photo is kept as the reference.
+fn mark_near_duplicate_photos(
+    planned: &mut [PlannedItem],
+    summary: &mut PlanSummary,
+    ranker: &Ranker,
+    threshold: u32,
+) {
+    let mut candidates: Vec<(usize, perceptual::PhotoFingerprint)> = planned
+        .par_iter()
+        .enumerate()
+        .filter(|(_, item)| {
+            matches!(item.kind, MediaKind::Photo)
+                && item.duplicate_of.is_none()
+                && item.already_in_library.is_none()
+        })
+        .filter_map(|(i, item)| {
+            perceptual::photo_fingerprint(Path::new(&item.src)).map(|fp| (i, fp))
+        })
+        .collect();
+
+    for (i, fp) in &candidates {
+        planned[*i].width = Some(fp.width);
+        planned[*i].height = Some(fp.height);
+    }
+    candidates.sort_by(|(a, _), (b, _)| ranker.compare(&planned[*a], &planned[*b]));
+
+    let hashes: Vec<u64> = candidates.iter().map(|(_, fp)| fp.dhash).collect();
+    for (i, canon, distance) in perceptual::cluster_by_hamming(&hashes, threshold) {
+        let (ci, ii) = (candidates[canon].0, candidates[i].0);
+        if planned[ci].canonical_reason.is_none() {
+            planned[ci].canonical_reason = Some(ranker.reason(&planned[ci], &planned[ii]));
+        }
+        let canon_src = planned[ci].src.clone();
+        let item = &mut planned[ii];
+        item.near_duplicate_of = Some(canon_src);
+        item.similarity = Some(perceptual::similarity(distance, 64));
+        summary.near_duplicate_photos += 1;
+    }
+}
+
+/// Groups clips that are the same footage in different encodings, e.g. a
+/// camera original and a phone re-share, by duration and sampled frame
+/// hashes. The best-ranked clip of each group is the reference.
+fn mark_near_duplicate_videos(
+    planned: &mut [PlannedItem],
+    summary: &mut PlanSummary,
+    ranker: &Ranker,
+    threshold: u32,
+    ffmpeg_slots: &Semaphore,
+) {
+    let mut candidates: Vec<(usize, perceptual::VideoFingerprint)> = planned
+        .par_iter()
+        .enumerate()
+        .filter(|(_, item)| {
+            matches!(item.kind, MediaKind::Video)
+                && item.duplicate_of.is_none()
+                && item.already_in_library.is_none()
+        })
+        .filter_map(|(i, item)| {
+            let duration = item.duration_secs?;
+            let _slot = ffmpeg_slots.acquire();
+            perceptual::video_fingerprint(Path::new(&item.src), duration).map(|fp| (i, fp))
+        })
+        .collect();
+    candidates.sort_by(|(a, _), (b, _)| ranker.compare(&planned[*a], &planned[*b]));
+
+    let fingerprints: Vec<&perceptual::VideoFingerprint> =
+        candidates.iter().map(|(_, fp)| fp).collect();
+    let groups = perceptual::cluster(&fingerprints, threshold, |a, b| {
+        perceptual::video_distance(a, b)
+    });
+    for (i, canon, distance) in groups {
+        let (ci, ii) = (candidates[canon].0, candidates[i].0);
+        if planned[ci].canonical_reason.is_none() {
+            planned[ci].canonical_reason = Some(ranker.reason(&planned[ci], &planned[ii]));
+        }
+        let canon_src = planned[ci].src.clone();
+        let item = &mut planned[ii];
+        item.near_duplicate_of = Some(canon_src);
+        item.similarity = Some(perceptual::similarity(distance, 64));
+        summary.near_duplicate_videos += 1;
+    }
+}
+
+/// Timestamp names collide for bursts shot within the same second. Within each
+/// colliding group, use the EXIF sub-second when every item has a distinct one,
+/// otherwise fall back to a counter. Duplicates and items already in the
+/// library are left alone here; duplicates get their own pass below.
+fn disambiguate_timestamp_names(planned: &mut [PlannedItem], subsec_ms: &HashMap<String, u32>) {
+    let mut by_dst: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+    for (i, item) in planned.iter().enumerate() {
+        if item.duplicate_of.is_none() && item.already_in_library.is_none() {
+            by_dst.entry(item.dst.clone()).or_default().push(i);
+        }
+    }
+
+    for (dst, mut group) in by_dst {
+        if group.len() < 2 {
+            continue;
+        }
+
+        let millis: Vec<Option<u32>> = group
+            .iter()
+            .map(|&i| subsec_ms.get(&planned[i].src).copied())
+            .collect();
+        let distinct: HashSet<u32> = millis.iter().flatten().copied().collect();
+        let use_subsec = millis.iter().all(|m| m.is_some()) && distinct.len() == group.len();
+
+        group.sort_by(|&a, &b| {
+            let ka = (subsec_ms.get(&planned[a].src), &planned[a].src);
+            let kb = (subsec_ms.get(&planned[b].src), &planned[b].src);
+            ka.cmp(&kb)
+        });
+
+        let dst = PathBuf::from(dst);
+        let stem = safe_stem(&dst);
+        let ext = normalize_extension(&dst).unwrap_or_default();
+
+        for (n, i) in group.into_iter().enumerate() {
+            let suffix = if use_subsec {
+                format!("-{:03}", subsec_ms[&planned[i].src])
+            } else if n == 0 {
+                continue;
+            } else {
+                format!("_{n}")
+            };
+            planned[i].dst = dst
+                .with_file_name(format!("{stem}{suffix}.{ext}"))
+                .to_string_lossy()
+                .to_string();
+        }
+    }
+}
+
+/// An exact duplicate with the same name and date as its canonical copy is
+/// planned onto the same destination, where apply finds the canonical's
+/// output and never links it. Moves each such duplicate to a free numbered
+/// name beside it, as the timestamp counter does.
+fn disambiguate_duplicate_names(planned: &mut [PlannedItem]) {
+    let mut taken: HashSet<String> = planned
+        .iter()
+        .filter(|i| i.duplicate_of.is_none())
+        .map(|i| i.dst.clone())
+        .collect();
+
+    for item in planned.iter_mut().filter(|i| i.duplicate_of.is_some()) {
+        if taken.insert(item.dst.clone()) {
+            continue;
+        }
+        let dst = PathBuf::from(&item.dst);
+        let stem = safe_stem(&dst);
+        let ext = normalize_extension(&dst).unwrap_or_default();
+        let free = (1..)
+            .map(|n| {
+                dst.with_file_name(format!("{stem}_{n}.{ext}"))
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .find(|candidate| !taken.contains(candidate))
+            .expect("unbounded counter");
+        taken.insert(free.clone());
+        item.dst = free;
+    }
+}
+
+fn action_for_video(path: &Path, extensions: &ExtensionMap) -> Action {
+    if extensions.needs_conversion(path) {
+        Action::ConvertVideo
+    } else {
+        Action::Copy
     }
 }
 
-fn mark_input_duplicates(planned: &mut [PlannedItem], summary: &mut PlanSummary) -> Result<()> {
+fn mark_input_duplicates(
+    planned: &mut [PlannedItem],
+    summary: &mut PlanSummary,
+    ranker: &Ranker,
+    cache: &HashCache,
+) -> Result<()> {
     let mut paths: Vec<PathBuf> = Vec::new();
-    for item in planned.iter() {
+    let mut index_of: HashMap<PathBuf, usize> = HashMap::new();
+    for (i, item) in planned.iter().enumerate() {
         match item.kind {
             MediaKind::Photo | MediaKind::Video => {
                 paths.push(PathBuf::from(&item.src));
+                index_of.insert(PathBuf::from(&item.src), i);
             }
             _ => {}
         }
     }
 
-    let dup_groups = deduplicate::find_exact_duplicates(&paths)?;
+    let dup_groups = deduplicate::find_exact_duplicates(&paths, cache)?;
 
     let mut duplicate_of: HashMap<String, String> = HashMap::new();
     let mut hash_of: HashMap<String, String> = HashMap::new();
 
-    for (h, mut group) in dup_groups {
-        group.sort();
-        let canonical = group[0].clone();
-        hash_of.insert(canonical.to_string_lossy().to_string(), h.clone());
+    for (h, group) in dup_groups {
+        let mut group: Vec<usize> = group.iter().map(|p| index_of[p]).collect();
+        group.sort_by(|&a, &b| ranker.compare(&planned[a], &planned[b]));
 
-        for p in group.into_iter().skip(1) {
-            duplicate_of.insert(
-                p.to_string_lossy().to_string(),
-                canonical.to_string_lossy().to_string(),
-            );
-            hash_of.insert(p.to_string_lossy().to_string(), h.clone());
+        let canonical = planned[group[0]].src.clone();
+        planned[group[0]].canonical_reason =
+            Some(ranker.reason(&planned[group[0]], &planned[group[1]]));
+        hash_of.insert(canonical.clone(), h.clone());
+
+        for i in group.into_iter().skip(1) {
+            duplicate_of.insert(planned[i].src.clone(), canonical.clone());
+            hash_of.insert(planned[i].src.clone(), h.clone());
         }
     }
 
@@ -163,9 +591,6 @@ fn mark_input_duplicates(planned: &mut [PlannedItem], summary: &mut PlanSummary)
             continue;
         }
 
-        let p = PathBuf::from(&item.src);
-        item.size_bytes = std::fs::metadata(&p).ok().map(|m| m.len());
-
         if let Some(h) = hash_of.get(&item.src) {
             item.content_hash = Some(h.clone());
         }
@@ -183,94 +608,301 @@ fn mark_input_duplicates(planned: &mut [PlannedItem], summary: &mut PlanSummary)
     Ok(())
 }
 
-pub fn build_plan(root: &Path, out_root: &Path) -> Result<(Vec<PlannedItem>, PlanSummary)> {
-    let mut planned: Vec<PlannedItem> = Vec::new();
-    let mut summary = PlanSummary::new();
+/// Fills in `content_hash` for photos and videos that duplicate detection
+/// did not need to hash.
+fn hash_remaining_items(planned: &mut [PlannedItem], cache: &HashCache) -> Result<()> {
+    let mut remaining: Vec<&mut PlannedItem> = planned
+        .iter_mut()
+        .filter(|i| matches!(i.kind, MediaKind::Photo | MediaKind::Video))
+        .filter(|i| i.content_hash.is_none())
+        .collect();
+    let paths: Vec<&Path> = remaining.iter().map(|i| Path::new(&i.src)).collect();
+    cache.start_stage("Hashing", &paths);
+    remaining.par_iter_mut().try_for_each(|item| -> Result<()> {
+        item.content_hash = Some(cache.hash(Path::new(&item.src))?);
+        Ok(())
+    })
+}
 
-    let mut dvd_roots: HashSet<PathBuf> = HashSet::new();
+fn summarize_root(label: &str, planned: &[PlannedItem], filter: &ScanFilter) -> RootSummary {
+    let mut rs = RootSummary {
+        excluded_dirs: filter.excluded.dirs,
+        excluded_files: filter.excluded.files,
+        ..RootSummary::default()
+    };
 
-    for entry in WalkDir::new(root) {
-        let entry = match entry {
-            Ok(e) => e,
-            Err(_) => continue,
-        };
+    for item in planned
+        .iter()
+        .filter(|i| i.source_root.as_deref() == Some(label))
+    {
+        rs.planned += 1;
+        match item.kind {
+            MediaKind::Photo => rs.photos += 1,
+            MediaKind::Video => rs.videos += 1,
+            MediaKind::Dvd => rs.dvds += 1,
+        }
+        if item.duplicate_of.is_some() {
+            rs.duplicates += 1;
+        }
+        if item.already_in_library.is_some() {
+            rs.already_in_library += 1;
+        }
+    }
 
-        let path = entry.path();
+    rs
+}
 
-        if entry.file_type().is_dir() {
-            if let Some(dvd_root) = dvd::dvd_root_from_video_ts_dir(path) {
-                dvd_roots.insert(dvd_root);
-            }
-            continue;
-        }
+/// A media file found by the walk, waiting for its metadata to be read.
+struct Candidate<'a> {
+    path: PathBuf,
+    kind: Kind,
+    size: Option<u64>,
+    root: &'a InputRoot,
+}
 
-        if !entry.file_type().is_file() {
-            continue;
-        }
+/// Reads dates and dimensions for one file. Runs on the worker pool; the
+/// semaphore bounds concurrent ffprobe processes separately from I/O threads.
+fn plan_file(
+    c: &Candidate,
+    out_root: &Path,
+    options: &PlanOptions,
+    ffprobe_slots: &Semaphore,
+) -> Result<(PlannedItem, Option<u32>)> {
+    let path = c.path.as_path();
+    let mut item = PlannedItem {
+        kind: MediaKind::Photo,
+        action: Action::Copy,
+        src: path.to_string_lossy().to_string(),
+        dst: String::new(),
+        best_dt: None,
+        date_source: DateSource::None,
+        size_bytes: c.size,
+        content_hash: None,
+        duplicate_of: None,
+        original_name: file_name_string(path),
+        source_root: Some(c.root.label.clone()),
+        near_duplicate_of: None,
+        similarity: None,
+        width: None,
+        height: None,
+        duration_secs: None,
+        reencoded: None,
+        canonical_reason: None,
+        already_in_library: None,
+    };
 
-        if dvd::is_inside_video_ts(path) {
-            continue;
+    let dt = match c.kind {
+        Kind::Photo => {
+            let exif = if is_jpeg(path) {
+                photo::read_exif(path)?
+            } else {
+                None
+            };
+            let (dt, source) = best_datetime_for_photo(path, exif.as_ref());
+            let dims = image::image_dimensions(path).ok();
+
+            item.date_source = source;
+            item.width = dims.map(|d| d.0);
+            item.height = dims.map(|d| d.1);
+            item.reencoded = Some(is_jpeg(path) && exif.is_none());
+            dt
+        }
+        Kind::Video => {
+            let probe = {
+                let _slot = ffprobe_slots.acquire();
+                video::ffprobe(path)?
+            };
+            let (dt, source) = best_datetime_for_video(path, probe.as_ref());
+
+            item.kind = MediaKind::Video;
+            item.action = action_for_video(path, &options.extensions);
+            item.date_source = source;
+            item.width = probe.as_ref().and_then(|p| p.width);
+            item.height = probe.as_ref().and_then(|p| p.height);
+            item.duration_secs = probe.as_ref().and_then(|p| p.duration_secs);
+            item.reencoded = probe.as_ref().map(|p| p.is_reencode());
+            dt
         }
+        Kind::Ignore => unreachable!("ignored files are not candidates"),
+    };
 
-        match classify(path) {
-            Kind::Photo => {
-                summary.photos += 1;
+    item.best_dt = dt.map(format_dt);
+    item.dst = plan_dst(out_root, item.kind, path, dt, options)
+        .to_string_lossy()
+        .to_string();
 
-                let (dt, source) = best_datetime_for_file(path)?;
-                if dt.is_none() {
-                    summary.missing_date += 1;
+    let subsec_ms = dt
+        .filter(|dt| dt.nanosecond() != 0)
+        .map(|dt| dt.nanosecond() / 1_000_000);
+
+    Ok((item, subsec_ms))
+}
+
+pub fn build_plan(
+    roots: &[InputRoot],
+    out_root: &Path,
+    options: &PlanOptions,
+    progress: &Arc<Progress>,
+) -> Result<(Vec<PlannedItem>, PlanSummary)> {
+    check_input_roots(roots)?;
+    let pool = workers::thread_pool(options.threads)?;
+    let result = pool.install(|| build_plan_in_pool(roots, out_root, options, progress));
+    progress.finish();
+    result
+}
+
+fn build_plan_in_pool(
+    roots: &[InputRoot],
+    out_root: &Path,
+    options: &PlanOptions,
+    progress: &Arc<Progress>,
+) -> Result<(Vec<PlannedItem>, PlanSummary)> {
+    let mut summary = PlanSummary::new();
+
+    let mut dvd_roots: BTreeMap<PathBuf, String> = BTreeMap::new();
+    let mut filters: Vec<ScanFilter> = Vec::new();
+    let mut candidates: Vec<Candidate> = Vec::new();
+
+    // The walk itself stays sequential so the manifest keeps walk order.
+    progress.stage("Scanning", None, None);
+    for input in roots {
+        let root = input.path.as_path();
+        let mut filter = ScanFilter::new(&options.scan, root, out_root)?;
+
+        for entry in WalkDir::new(root)
+            .into_iter()
+            .filter_entry(|e| filter.allows(e))
+        {
+            let entry = match entry {
+                Ok(e) => e,
+                Err(_) => continue,
+            };
+
+            let path = entry.path();
+
+            if entry.file_type().is_dir() {
+                if let Some(dvd_root) = dvd::dvd_root_from_video_ts_dir(path) {
+                    dvd_roots.insert(dvd_root, input.label.clone());
                 }
+                continue;
+            }
 
-                let dst = plan_dst(out_root, MediaKind::Photo, path, dt);
-                planned.push(PlannedItem {
-                    kind: MediaKind::Photo,
-                    action: Action::Copy,
-                    src: path.to_string_lossy().to_string(),
-                    dst: dst.to_string_lossy().to_string(),
-                    best_dt: dt.map(format_dt),
-                    date_source: source,
-                    size_bytes: None,
-                    content_hash: None,
-                    duplicate_of: None,
-                });
-
-                summary.planned += 1;
+            if !entry.file_type().is_file() {
+                continue;
             }
-            Kind::Video => {
-                summary.videos += 1;
 
-                let (dt, source) = best_datetime_for_file(path)?;
-                if dt.is_none() {
-                    summary.missing_date += 1;
-                }
+            if dvd::is_inside_video_ts(path) {
+                continue;
+            }
 
-                let action = action_for_video(path);
-                if matches!(action, Action::ConvertVideo) {
-                    summary.need_convert_video += 1;
-                }
+            let kind = classify(path, &options.extensions);
+            if matches!(kind, Kind::Ignore) {
+                continue;
+            }
+
+            candidates.push(Candidate {
+                path: path.to_path_buf(),
+                kind,
+                size: entry.metadata().ok().map(|m| m.len()),
+                root: input,
+            });
+            progress.advance(1, 0);
+        }
+
+        summary.excluded_dirs += filter.excluded.dirs;
+        summary.excluded_files += filter.excluded.files;
+        filters.push(filter);
+    }
+
+    let ffprobe_slots = Semaphore::new(options.ffprobe_processes);
+    progress.stage("Reading metadata", Some(candidates.len() as u64), None);
+    let results: Vec<(PlannedItem, Option<u32>)> = candidates
+        .par_iter()
+        .map(|c| {
+            let planned = plan_file(c, out_root, options, &ffprobe_slots);
+            progress.advance(1, 0);
+            planned
+        })
+        .collect::<Result<_>>()?;
+
+    let mut planned: Vec<PlannedItem> = Vec::with_capacity(results.len());
+    let mut subsec_ms: HashMap<String, u32> = HashMap::new();
+    for (item, subsec) in results {
+        match item.kind {
+            MediaKind::Photo => summary.photos += 1,
+            MediaKind::Video => summary.videos += 1,
+            MediaKind::Dvd => {}
+        }
+        if item.best_dt.is_none() {
+            summary.missing_date += 1;
+        }
+        if matches!(item.action, Action::ConvertVideo) {
+            summary.need_convert_video += 1;
+        }
+        if let Some(ms) = subsec {
+            subsec_ms.insert(item.src.clone(), ms);
+        }
+        summary.planned += 1;
+        planned.push(item);
+    }
 
-                let dst = plan_dst(out_root, MediaKind::Video, path, dt);
-                planned.push(PlannedItem {
-                    kind: MediaKind::Video,
-                    action,
-                    src: path.to_string_lossy().to_string(),
-                    dst: dst.to_string_lossy().to_string(),
-                    best_dt: dt.map(format_dt),
-                    date_source: source,
-                    size_bytes: None,
-                    content_hash: None,
-                    duplicate_of: None,
-                });
-
-                summary.planned += 1;
+    // Conversions keep their sources: the output cannot be verified against them.
+    if options.move_sources {
+        for item in planned.iter_mut() {
+            if matches!(item.action, Action::Copy) {
+                item.action = Action::Move;
             }
-            _ => {}
         }
     }
 
-    mark_input_duplicates(&mut planned, &mut summary)?;
+    let labels: Vec<String> = roots.iter().map(|r| r.label.clone()).collect();
+    let ranker = Ranker::new(&options.rank, &labels);
+
+    let cache = match &options.hash_cache {
+        Some(path) => HashCache::open(path)?,
+        None => HashCache::in_memory(),
+    }
+    .with_progress(Arc::clone(progress));
+
+    // Duplicates are detected across every root together.
+    if options.detect_duplicates {
+        mark_input_duplicates(&mut planned, &mut summary, &ranker, &cache)?;
+    }
+
+    if options.check_library {
+        mark_already_in_library(&mut planned, &mut summary, out_root, &cache)?;
+    }
 
-    for dvd_root in dvd_roots {
+    if options.hash_all {
+        hash_remaining_items(&mut planned, &cache)?;
+    }
+
+    progress.finish();
+    cache.save()?;
+    let stats = cache.stats();
+    summary.hash_cache_hits = stats.hits;
+    summary.hash_cache_misses = stats.misses;
+
+    if let Some(threshold) = options.near_duplicate_threshold {
+        mark_near_duplicate_photos(&mut planned, &mut summary, &ranker, threshold);
+    }
+
+    if let Some(threshold) = options.near_video_threshold {
+        mark_near_duplicate_videos(
+            &mut planned,
+            &mut summary,
+            &ranker,
+            threshold,
+            &ffprobe_slots,
+        );
+    }
+
+    if options.naming == NamingMode::Timestamp {
+        disambiguate_timestamp_names(&mut planned, &subsec_ms);
+    }
+    disambiguate_duplicate_names(&mut planned);
+
+    for (dvd_root, label) in dvd_roots {
         summary.dvds += 1;
 
         let (dt, source) = best_datetime_for_dvd(&dvd_root);
@@ -280,7 +912,7 @@ pub fn build_plan(root: &Path, out_root: &Path) -> Result<(Vec<PlannedItem>, Pla
 
         let _vobs = dvd::dvd_all_content_vobs(&dvd_root)?;
 
-        let dst = plan_dst(out_root, MediaKind::Dvd, &dvd_root, dt);
+        let dst = plan_dst(out_root, MediaKind::Dvd, &dvd_root, dt, options);
         planned.push(PlannedItem {
             kind: MediaKind::Dvd,
             action: Action::ConvertDvd,
@@ -291,11 +923,27 @@ pub fn build_plan(root: &Path, out_root: &Path) -> Result<(Vec<PlannedItem>, Pla
             size_bytes: None,
             content_hash: None,
             duplicate_of: None,
+            original_name: file_name_string(&dvd_root),
+            source_root: Some(label),
+            near_duplicate_of: None,
+            similarity: None,
+            width: None,
+            height: None,
+            duration_secs: None,
+            reencoded: None,
+            canonical_reason: None,
+            already_in_library: None,
         });
 
         summary.need_convert_dvd += 1;
         summary.planned += 1;
     }
 
+    summary.by_root = roots
+        .iter()
+        .zip(&filters)
+        .map(|(r, f)| (r.label.clone(), summarize_root(&r.label, &planned, f)))
+        .collect();
+
     Ok((planned, summary))
 }
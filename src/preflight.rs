@@ -0,0 +1,239 @@
+use crate::apply::is_selected;
+use crate::atomic;
+use crate::deduplicate::blake3_hash_file;
+use crate::dvd::dvd_all_content_vobs;
+use crate::journal::Journal;
+use crate::plan::{Action, PlannedItem};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// What apply does when the pre-flight check finds a problem.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpaceCheck {
+    /// Refuse to start.
+    #[default]
+    Fail,
+    /// Print the problems and apply anyway.
+    Warn,
+    /// Skip the check.
+    Off,
+}
+
+/// Space an apply run is expected to need under `out_root`, and whether it
+/// can have it.
+pub struct Preflight {
+    pub out_root: PathBuf,
+    pub copies: u64,
+    pub copy_bytes: u64,
+    pub conversions: u64,
+    /// Estimated from duration and `video_kbps`, or the source size when the
+    /// duration is unknown.
+    pub conversion_bytes: u64,
+    /// Items whose size could not be determined and are not counted.
+    pub unknown_size: u64,
+    /// `None` when the platform cannot report it.
+    pub free_bytes: Option<u64>,
+    pub write_error: Option<String>,
+}
+
+impl Preflight {
+    pub fn needed_bytes(&self) -> u64 {
+        self.copy_bytes + self.conversion_bytes
+    }
+
+    /// Reasons not to start, empty when the run looks safe.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if let Some(e) = &self.write_error {
+            problems.push(format!("{} is not writable: {e}", self.out_root.display()));
+        }
+        if let Some(free) = self.free_bytes
+            && self.needed_bytes() > free
+        {
+            problems.push(format!(
+                "need {} on {}, only {} free",
+                human_bytes(self.needed_bytes()),
+                self.out_root.display(),
+                human_bytes(free)
+            ));
+        }
+        problems
+    }
+}
+
+pub fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+/// `out_root` may not exist yet; space and permissions come from the
+/// directory it will be created in.
+fn existing_ancestor(path: &Path) -> Option<&Path> {
+    path.ancestors()
+        .find(|p| p.is_dir())
+        .or_else(|| Some(Path::new(".")).filter(|_| path.is_relative()))
+}
+
+#[cfg(target_os = "linux")]
+fn free_bytes(dir: &Path) -> Option<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(dir.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `c_path` is NUL-terminated and `stat` is a valid out pointer.
+    let rc = unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) };
+    (rc == 0).then(|| stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn free_bytes(_dir: &Path) -> Option<u64> {
+    None
+}
+
+/// Creates and removes a temp file, named like apply's own so a leftover is
+/// cleaned up with the other stale temps.
+fn probe_writable(dir: &Path) -> io::Result<()> {
+    let probe = atomic::temp_path(&dir.join("preflight"));
+    fs::File::create(&probe)?;
+    fs::remove_file(&probe)
+}
+
+/// For dry runs, which must not create anything: asks the OS whether the
+/// directory is writable instead.
+#[cfg(target_os = "linux")]
+fn check_writable(dir: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(dir.as_os_str().as_bytes())?;
+    // SAFETY: `c_path` is NUL-terminated.
+    let rc = unsafe { libc::access(c_path.as_ptr(), libc::W_OK) };
+    if rc == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn check_writable(dir: &Path) -> io::Result<()> {
+    if fs::metadata(dir)?.permissions().readonly() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "directory is read-only",
+        ));
+    }
+    Ok(())
+}
+
+/// Size of the output apply would write for `item`, if it can be told.
+fn output_estimate(item: &PlannedItem, video_kbps: u32) -> Option<u64> {
+    match item.action {
+        Action::Copy | Action::Move => item.size_bytes,
+        Action::ConvertVideo => match item.duration_secs {
+            Some(secs) => Some((secs * f64::from(video_kbps) * 1000.0 / 8.0) as u64),
+            None => item.size_bytes,
+        },
+        Action::ConvertDvd => {
+            let vobs = dvd_all_content_vobs(Path::new(&item.src)).ok()?;
+            Some(
+                vobs.iter()
+                    .filter_map(|v| fs::metadata(v).ok())
+                    .map(|m| m.len())
+                    .sum(),
+            )
+        }
+    }
+}
+
+/// Whether `dst` already holds a copy's content, so apply will leave it be.
+/// A different file there sends the copy to a numbered name instead.
+fn already_written(item: &PlannedItem) -> bool {
+    if !matches!(item.action, Action::Copy | Action::Move) {
+        return false;
+    }
+    let dst = Path::new(&item.dst);
+    let Ok(meta) = fs::metadata(dst) else {
+        return false;
+    };
+    Some(meta.len()) == item.size_bytes
+        && item.content_hash.as_ref().is_none_or(|expected| {
+            blake3_hash_file(dst).is_ok_and(|h| h.to_hex().as_str() == expected)
+        })
+}
+
+/// Sums what the items a run will apply (see `is_selected`) will write:
+/// duplicates, items already in the library and outputs already in place
+/// take no new space. A `dry_run` check leaves no probe file behind.
+pub fn check(
+    items: &[PlannedItem],
+    out_root: &Path,
+    journal: &Journal,
+    video_kbps: u32,
+    retry_failed: bool,
+    dry_run: bool,
+) -> Preflight {
+    let mut pre = Preflight {
+        out_root: out_root.to_path_buf(),
+        copies: 0,
+        copy_bytes: 0,
+        conversions: 0,
+        conversion_bytes: 0,
+        unknown_size: 0,
+        free_bytes: None,
+        write_error: None,
+    };
+
+    for item in items {
+        if item.duplicate_of.is_some()
+            || item.already_in_library.is_some()
+            || !is_selected(journal.state(item), retry_failed)
+            || already_written(item)
+        {
+            continue;
+        }
+        let estimate = output_estimate(item, video_kbps);
+        match item.action {
+            Action::Copy | Action::Move => {
+                pre.copies += 1;
+                pre.copy_bytes += estimate.unwrap_or(0);
+            }
+            Action::ConvertVideo | Action::ConvertDvd => {
+                pre.conversions += 1;
+                pre.conversion_bytes += estimate.unwrap_or(0);
+            }
+        }
+        if estimate.is_none() {
+            pre.unknown_size += 1;
+        }
+    }
+
+    match existing_ancestor(out_root) {
+        Some(dir) => {
+            pre.free_bytes = free_bytes(dir);
+            let writable = if dry_run {
+                check_writable(dir)
+            } else {
+                probe_writable(dir)
+            };
+            pre.write_error = writable.err().map(|e| e.to_string());
+        }
+        None => pre.write_error = Some("no existing parent directory".into()),
+    }
+    pre
+}
@@ -0,0 +1,171 @@
+use crate::preflight::human_bytes;
+use std::collections::BTreeMap;
+use std::io::{self, IsTerminal, Write};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How often the status line is redrawn on a terminal.
+const TTY_INTERVAL: Duration = Duration::from_millis(200);
+/// How often a progress line is printed when stderr is not a terminal.
+const PLAIN_INTERVAL: Duration = Duration::from_secs(10);
+
+struct Stage {
+    name: &'static str,
+    started: Instant,
+    done: u64,
+    total: Option<u64>,
+    bytes: u64,
+    total_bytes: Option<u64>,
+    /// Per-job detail, e.g. how far each running conversion has got.
+    active: BTreeMap<String, String>,
+    last_draw: Option<Instant>,
+}
+
+/// Live progress on stderr: one line redrawn in place on a terminal, or a
+/// plain line every few seconds when output is redirected.
+pub struct Progress {
+    enabled: bool,
+    tty: bool,
+    stage: Mutex<Option<Stage>>,
+}
+
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    if secs >= 3600 {
+        format!("{}h{:02}m", secs / 3600, secs % 3600 / 60)
+    } else {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    }
+}
+
+impl Stage {
+    fn eta(&self) -> Option<Duration> {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        // Bytes predict better than item counts when sizes vary widely.
+        let (done, total) = match self.total_bytes {
+            Some(total) if total > 0 && self.bytes > 0 => (self.bytes, total),
+            _ => (self.done, self.total?),
+        };
+        if done == 0 || elapsed < 1.0 {
+            return None;
+        }
+        let remaining = total.saturating_sub(done) as f64 * elapsed / done as f64;
+        Some(Duration::from_secs_f64(remaining))
+    }
+
+    fn line(&self) -> String {
+        let mut line = format!("{}: {}", self.name, self.done);
+        if let Some(total) = self.total {
+            line.push_str(&format!("/{total}"));
+        }
+        if self.bytes > 0 || self.total_bytes.is_some() {
+            line.push_str(&format!(", {}", human_bytes(self.bytes)));
+            if let Some(total) = self.total_bytes {
+                line.push_str(&format!("/{}", human_bytes(total)));
+            }
+            let elapsed = self.started.elapsed().as_secs_f64();
+            if elapsed >= 1.0 {
+                let rate = (self.bytes as f64 / elapsed) as u64;
+                line.push_str(&format!(" ({}/s)", human_bytes(rate)));
+            }
+        }
+        if let Some(eta) = self.eta() {
+            line.push_str(&format!(", ETA {}", format_duration(eta)));
+        }
+        for detail in self.active.values() {
+            line.push_str(&format!(" | {detail}"));
+        }
+        line
+    }
+}
+
+impl Progress {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            tty: io::stderr().is_terminal(),
+            stage: Mutex::new(None),
+        }
+    }
+
+    /// Ends the current stage, if any, and starts counting towards `total`
+    /// items and `total_bytes` bytes, when known.
+    pub fn stage(&self, name: &'static str, total: Option<u64>, total_bytes: Option<u64>) {
+        if !self.enabled {
+            return;
+        }
+        self.finish();
+        *self.stage.lock().unwrap() = Some(Stage {
+            name,
+            started: Instant::now(),
+            done: 0,
+            total,
+            bytes: 0,
+            total_bytes,
+            active: BTreeMap::new(),
+            last_draw: None,
+        });
+    }
+
+    pub fn advance(&self, items: u64, bytes: u64) {
+        if !self.enabled {
+            return;
+        }
+        let mut guard = self.stage.lock().unwrap();
+        if let Some(stage) = guard.as_mut() {
+            stage.done += items;
+            stage.bytes += bytes;
+            self.draw(stage, false);
+        }
+    }
+
+    /// Shows `detail` for the job `key` until it is set to `None`.
+    pub fn set_active(&self, key: &str, detail: Option<String>) {
+        if !self.enabled {
+            return;
+        }
+        let mut guard = self.stage.lock().unwrap();
+        if let Some(stage) = guard.as_mut() {
+            match detail {
+                Some(d) => stage.active.insert(key.to_string(), d),
+                None => stage.active.remove(key),
+            };
+            self.draw(stage, false);
+        }
+    }
+
+    /// Prints the final state of the current stage.
+    pub fn finish(&self) {
+        if !self.enabled {
+            return;
+        }
+        if let Some(mut stage) = self.stage.lock().unwrap().take() {
+            stage.active.clear();
+            self.draw(&mut stage, true);
+            if self.tty {
+                eprintln!();
+            }
+        }
+    }
+
+    fn draw(&self, stage: &mut Stage, force: bool) {
+        let interval = if self.tty {
+            TTY_INTERVAL
+        } else {
+            PLAIN_INTERVAL
+        };
+        let due = stage.last_draw.is_none_or(|t| t.elapsed() >= interval);
+        if !force && !due {
+            return;
+        }
+        stage.last_draw = Some(Instant::now());
+
+        let mut err = io::stderr().lock();
+        if self.tty {
+            let _ = write!(err, "\r\x1b[K{}", stage.line());
+        } else {
+            let _ = writeln!(err, "{}", stage.line());
+        }
+        let _ = err.flush();
+    }
+}
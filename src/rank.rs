@@ -0,0 +1,157 @@
+use crate::plan::PlannedItem;
+use crate::time::DateSource;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+/// One rule for choosing which copy in a duplicate group is kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RankCriterion {
+    /// Capture dates from EXIF/ffprobe beat file mtimes.
+    DateSource,
+    /// Camera originals beat messenger re-saves and ffmpeg re-encodes.
+    Original,
+    /// More pixels wins.
+    Resolution,
+    /// Earlier input roots (or `preferred_roots`) win.
+    Root,
+    /// Lexicographically smallest source path; always applied last.
+    Path,
+}
+
+impl RankCriterion {
+    fn name(self) -> &'static str {
+        match self {
+            RankCriterion::DateSource => "date_source",
+            RankCriterion::Original => "original",
+            RankCriterion::Resolution => "resolution",
+            RankCriterion::Root => "root",
+            RankCriterion::Path => "path",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RankPolicy {
+    pub order: Vec<RankCriterion>,
+    /// Root labels to prefer, ahead of the order roots were given in.
+    pub preferred_roots: Vec<String>,
+}
+
+impl Default for RankPolicy {
+    fn default() -> Self {
+        Self {
+            order: vec![
+                RankCriterion::DateSource,
+                RankCriterion::Original,
+                RankCriterion::Resolution,
+                RankCriterion::Root,
+            ],
+            preferred_roots: Vec::new(),
+        }
+    }
+}
+
+/// A `RankPolicy` bound to the input roots of one plan.
+pub struct Ranker {
+    order: Vec<RankCriterion>,
+    root_order: Vec<String>,
+}
+
+fn date_source_score(s: DateSource) -> u8 {
+    match s {
+        DateSource::Exif | DateSource::Ffprobe => 2,
+        DateSource::Mtime => 1,
+        DateSource::None => 0,
+    }
+}
+
+fn pixels(item: &PlannedItem) -> u64 {
+    u64::from(item.width.unwrap_or(0)) * u64::from(item.height.unwrap_or(0))
+}
+
+impl Ranker {
+    pub fn new(policy: &RankPolicy, root_labels: &[String]) -> Self {
+        let mut root_order = policy.preferred_roots.clone();
+        root_order.extend(
+            root_labels
+                .iter()
+                .filter(|l| !policy.preferred_roots.contains(l))
+                .cloned(),
+        );
+
+        let mut order = policy.order.clone();
+        order.retain(|c| *c != RankCriterion::Path);
+        order.push(RankCriterion::Path);
+
+        Self { order, root_order }
+    }
+
+    fn root_rank(&self, item: &PlannedItem) -> usize {
+        item.source_root
+            .as_ref()
+            .and_then(|l| self.root_order.iter().position(|r| r == l))
+            .unwrap_or(usize::MAX)
+    }
+
+    /// `Less` means `a` is the better copy to keep.
+    fn compare_by(&self, c: RankCriterion, a: &PlannedItem, b: &PlannedItem) -> Ordering {
+        match c {
+            RankCriterion::DateSource => {
+                date_source_score(b.date_source).cmp(&date_source_score(a.date_source))
+            }
+            RankCriterion::Original => a
+                .reencoded
+                .unwrap_or(false)
+                .cmp(&b.reencoded.unwrap_or(false)),
+            RankCriterion::Resolution => pixels(b)
+                .cmp(&pixels(a))
+                .then(b.size_bytes.cmp(&a.size_bytes)),
+            RankCriterion::Root => self.root_rank(a).cmp(&self.root_rank(b)),
+            RankCriterion::Path => a.src.cmp(&b.src),
+        }
+    }
+
+    pub fn compare(&self, a: &PlannedItem, b: &PlannedItem) -> Ordering {
+        self.order
+            .iter()
+            .map(|&c| self.compare_by(c, a, b))
+            .find(|o| o.is_ne())
+            .unwrap_or(Ordering::Equal)
+    }
+
+    /// Why `winner` was kept over `runner_up`, for the manifest.
+    pub fn reason(&self, winner: &PlannedItem, runner_up: &PlannedItem) -> String {
+        let Some(c) = self
+            .order
+            .iter()
+            .copied()
+            .find(|&c| self.compare_by(c, winner, runner_up).is_ne())
+        else {
+            return "identical".to_string();
+        };
+
+        let detail = match c {
+            RankCriterion::DateSource => {
+                format!("{:?} over {:?}", winner.date_source, runner_up.date_source)
+            }
+            RankCriterion::Original => "original over re-encode".to_string(),
+            RankCriterion::Resolution => format!(
+                "{}x{} over {}x{}",
+                winner.width.unwrap_or(0),
+                winner.height.unwrap_or(0),
+                runner_up.width.unwrap_or(0),
+                runner_up.height.unwrap_or(0)
+            ),
+            RankCriterion::Root => format!(
+                "root {} over {}",
+                winner.source_root.as_deref().unwrap_or("?"),
+                runner_up.source_root.as_deref().unwrap_or("?")
+            ),
+            RankCriterion::Path => "first path".to_string(),
+        };
+
+        format!("{}: {detail}", c.name())
+    }
+}
@@ -1,7 +1,12 @@
+use crate::apply::Outcome;
+use crate::apply_log::{ApplyEvent, outcome_name};
+use crate::deduplicate::blake3_hash_file;
+use crate::journal::Journal;
 use crate::plan::{Action, MediaKind, PlannedItem};
+use crate::preflight::human_bytes;
 use anyhow::{Ok, Result};
 use std::collections::BTreeMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug)]
 pub struct ReportSummary {
@@ -9,14 +14,20 @@ pub struct ReportSummary {
     pub by_kind: BTreeMap<String, u64>,
     pub by_action: BTreeMap<String, u64>,
     pub by_date_source: BTreeMap<String, u64>,
+    pub by_root: BTreeMap<String, u64>,
     pub missing_date: u64,
     pub duplicates: u64,
+    pub near_duplicates: u64,
+    pub already_in_library: u64,
     pub by_year: BTreeMap<String, u64>,
     pub by_year_month: BTreeMap<String, u64>,
 
     pub outputs_exist: u64,
     pub outputs_missing: u64,
     pub outputs_zero_bytes: u64,
+    /// Outputs whose content no longer matches the planned `content_hash`,
+    /// or the hash the journal recorded when apply wrote them.
+    pub outputs_hash_mismatch: u64,
 }
 
 impl ReportSummary {
@@ -26,13 +37,17 @@ impl ReportSummary {
             by_kind: BTreeMap::new(),
             by_action: BTreeMap::new(),
             by_date_source: BTreeMap::new(),
+            by_root: BTreeMap::new(),
             missing_date: 0,
             duplicates: 0,
+            near_duplicates: 0,
+            already_in_library: 0,
             by_year: BTreeMap::new(),
             by_year_month: BTreeMap::new(),
             outputs_exist: 0,
             outputs_missing: 0,
             outputs_zero_bytes: 0,
+            outputs_hash_mismatch: 0,
         }
     }
 }
@@ -52,6 +67,7 @@ fn kind_str(k: MediaKind) -> &'static str {
 fn action_str(a: Action) -> &'static str {
     match a {
         Action::Copy => "Copy",
+        Action::Move => "Move",
         Action::ConvertVideo => "ConvertVideo",
         Action::ConvertDvd => "ConvertDvd",
     }
@@ -71,22 +87,31 @@ fn year_month_from_best_dt(best_dt: &Option<String>) -> Option<String> {
         .map(|s| s.to_string())
 }
 
+/// With `journal`, outputs an apply run wrote under another name (after a
+/// destination conflict) are validated where they really are, and outputs
+/// with no planned hash (conversions) against the hash apply recorded.
 pub fn build_report(
     items: &[PlannedItem],
     validate_outputs: bool,
+    journal: Option<&Journal>,
 ) -> Result<(ReportSummary, Vec<String>)> {
     let mut s = ReportSummary::new();
     let mut notes: Vec<String> = Vec::new();
 
     let mut missing_dates: Vec<&PlannedItem> = Vec::new();
     let mut duplicates: Vec<&PlannedItem> = Vec::new();
-    let mut missing_outputs: Vec<&PlannedItem> = Vec::new();
+    let mut near_duplicates: Vec<&PlannedItem> = Vec::new();
+    let mut missing_outputs: Vec<(&PlannedItem, PathBuf)> = Vec::new();
+    let mut mismatched_outputs: Vec<(&PlannedItem, PathBuf)> = Vec::new();
 
     for item in items {
         s.total += 1;
         bump(&mut s.by_kind, kind_str(item.kind));
         bump(&mut s.by_action, action_str(item.action));
         bump(&mut s.by_date_source, format!("{:?}", item.date_source));
+        if let Some(root) = &item.source_root {
+            bump(&mut s.by_root, root.as_str());
+        }
 
         if item.best_dt.is_none() {
             s.missing_date += 1;
@@ -98,6 +123,15 @@ pub fn build_report(
             duplicates.push(item);
         }
 
+        if item.already_in_library.is_some() {
+            s.already_in_library += 1;
+        }
+
+        if item.near_duplicate_of.is_some() {
+            s.near_duplicates += 1;
+            near_duplicates.push(item);
+        }
+
         if let Some(y) = year_from_best_dt(&item.best_dt) {
             bump(&mut s.by_year, y);
         } else {
@@ -111,16 +145,37 @@ pub fn build_report(
         }
 
         if validate_outputs {
-            let dst = PathBuf::from(&item.dst);
+            let dst = PathBuf::from(
+                journal
+                    .and_then(|j| j.written_to(item))
+                    .unwrap_or(&item.dst),
+            );
             if dst.exists() {
                 s.outputs_exist += 1;
                 let size = std::fs::metadata(&dst).map(|m| m.len()).unwrap_or(0);
                 if size == 0 {
                     s.outputs_zero_bytes += 1;
                 }
-            } else if item.duplicate_of.is_none() {
+                let planned_hash = item
+                    .content_hash
+                    .as_deref()
+                    .filter(|_| matches!(item.action, Action::Copy | Action::Move));
+                if item.duplicate_of.is_none()
+                    && item.already_in_library.is_none()
+                    && let Some(expected) =
+                        planned_hash.or_else(|| journal.and_then(|j| j.output_hash(item)))
+                    && blake3_hash_file(&dst)
+                        .map(|h| h.to_hex().to_string())
+                        .ok()
+                        .as_deref()
+                        != Some(expected)
+                {
+                    s.outputs_hash_mismatch += 1;
+                    mismatched_outputs.push((item, dst));
+                }
+            } else if item.duplicate_of.is_none() && item.already_in_library.is_none() {
                 s.outputs_missing += 1;
-                missing_outputs.push(item);
+                missing_outputs.push((item, dst));
             }
         }
     }
@@ -147,12 +202,40 @@ pub fn build_report(
         }
     }
 
+    if !near_duplicates.is_empty() {
+        notes.push("Near-duplicates (still applied):".to_string());
+        for it in near_duplicates {
+            notes.push(format!(
+                "    - {:?} src={} near {} (similarity {:.2})",
+                it.kind,
+                it.src,
+                it.near_duplicate_of.as_deref().unwrap_or("?"),
+                it.similarity.unwrap_or(0.0)
+            ));
+        }
+    }
+
     if validate_outputs && !missing_outputs.is_empty() {
         notes.push("Missing output (dst does not exist):".to_string());
-        for it in missing_outputs {
+        for (it, dst) in missing_outputs {
             notes.push(format!(
                 "    - {:?} {:?} dst={} (src={})",
-                it.kind, it.action, it.dst, it.src
+                it.kind,
+                it.action,
+                dst.display(),
+                it.src
+            ));
+        }
+    }
+
+    if !mismatched_outputs.is_empty() {
+        notes.push("Output content differs from the planned hash:".to_string());
+        for (it, dst) in mismatched_outputs {
+            notes.push(format!(
+                "    - {:?} dst={} (src={})",
+                it.kind,
+                dst.display(),
+                it.src
             ));
         }
     }
@@ -179,8 +262,17 @@ pub fn print_report(summary: &ReportSummary, notes: &[String], validate_outputs:
         println!("  {k:12} {v}");
     }
 
+    if !summary.by_root.is_empty() {
+        println!("\nBy input root:");
+        for (k, v) in &summary.by_root {
+            println!("  {k:12} {v}");
+        }
+    }
+
     println!("\nMissing date: {}", summary.missing_date);
     println!("Duplicates (input): {}", summary.duplicates);
+    println!("Near-duplicates: {}", summary.near_duplicates);
+    println!("Already in library: {}", summary.already_in_library);
 
     // Show “top-ish” years/months (BTreeMap is sorted; that's fine for browsing)
     println!("\nBy year (sorted):");
@@ -198,6 +290,7 @@ pub fn print_report(summary: &ReportSummary, notes: &[String], validate_outputs:
         println!("  Outputs exist:      {}", summary.outputs_exist);
         println!("  Outputs missing:    {}", summary.outputs_missing);
         println!("  Outputs zero-bytes: {}", summary.outputs_zero_bytes);
+        println!("  Hash mismatches:    {}", summary.outputs_hash_mismatch);
     }
 
     if !notes.is_empty() {
@@ -207,3 +300,67 @@ pub fn print_report(summary: &ReportSummary, notes: &[String], validate_outputs:
         }
     }
 }
+
+/// Summarises one apply run from its event log.
+pub fn print_apply_log(path: &Path, events: &[ApplyEvent]) {
+    let mut by_outcome: BTreeMap<String, u64> = BTreeMap::new();
+    let mut bytes = 0;
+    let mut busy_ms = 0;
+    let mut released = 0;
+    for e in events {
+        *by_outcome.entry(outcome_name(e.outcome)).or_insert(0) += 1;
+        bytes += e.bytes.unwrap_or(0);
+        busy_ms += e.duration_ms;
+        if e.released.is_some() {
+            released += 1;
+        }
+    }
+
+    println!("\n=== Apply Log ===");
+    println!("Log:    {}", path.display());
+    if let Some(first) = events.first() {
+        println!("Run:    {}", first.run_id);
+    }
+    println!("Events: {}", events.len());
+    for (k, v) in &by_outcome {
+        println!("  {k:20} {v}");
+    }
+    println!("Written:         {}", human_bytes(bytes));
+    println!("Item time:       {:.1}s", busy_ms as f64 / 1000.0);
+    println!("Sources removed: {released}");
+
+    let failed: Vec<&ApplyEvent> = events
+        .iter()
+        .filter(|e| e.outcome == Outcome::Failed)
+        .collect();
+    if !failed.is_empty() {
+        println!("\nFailures:");
+        for e in failed {
+            println!("  {} -> {}", e.src, e.dst);
+            for cause in &e.error {
+                println!("    {cause}");
+            }
+        }
+    }
+
+    let conflicts: Vec<&ApplyEvent> = events.iter().filter(|e| e.conflict.is_some()).collect();
+    if !conflicts.is_empty() {
+        println!("\nDestination conflicts:");
+        for e in conflicts {
+            match &e.written_to {
+                Some(written) => println!("  {} -> {} (renamed from {})", e.src, written, e.dst),
+                None => println!("  {} -> {} (overwrote existing file)", e.src, e.dst),
+            }
+        }
+    }
+
+    let warned: Vec<&ApplyEvent> = events.iter().filter(|e| !e.warnings.is_empty()).collect();
+    if !warned.is_empty() {
+        println!("\nWarnings:");
+        for e in warned {
+            for w in &e.warnings {
+                println!("  {}: {w}", e.src);
+            }
+        }
+    }
+}
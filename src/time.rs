@@ -1,14 +1,10 @@
-use anyhow::Result;
 use chrono::{DateTime, Local, NaiveDateTime};
 use serde::{Deserialize, Serialize};
 use std::{path::Path, time::SystemTime};
 
-use crate::{
-    classify::{Kind, classify, is_jpeg},
-    photo, video,
-};
+use crate::{photo::ExifInfo, video::ProbeInfo};
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum DateSource {
     Exif,
     Ffprobe,
@@ -27,30 +23,30 @@ pub fn format_dt(dt: NaiveDateTime) -> String {
     dt.format("%Y-%m-%d %H:%M:%S").to_string()
 }
 
-pub fn best_datetime_for_file(path: &Path) -> Result<(Option<NaiveDateTime>, DateSource)> {
-    match classify(path) {
-        Kind::Photo => {
-            if is_jpeg(path) {
-                if let Some(dt) = photo::exif_capture_datetime(path)? {
-                    return Ok((Some(dt), DateSource::Exif));
-                }
-            }
-            if let Some(dt) = file_mtime(path) {
-                return Ok((Some(dt), DateSource::Mtime));
-            }
-            Ok((None, DateSource::None))
-        }
-        Kind::Video => {
-            if let Some(dt) = video::ffprobe_creation_time(path)? {
-                return Ok((Some(dt), DateSource::Ffprobe));
-            }
-            if let Some(dt) = file_mtime(path) {
-                return Ok((Some(dt), DateSource::Mtime));
-            }
-            Ok((None, DateSource::None))
-        }
-        _ => Ok((None, DateSource::None)),
+pub fn best_datetime_for_photo(
+    path: &Path,
+    exif: Option<&ExifInfo>,
+) -> (Option<NaiveDateTime>, DateSource) {
+    if let Some(dt) = exif.and_then(|e| e.capture) {
+        return (Some(dt), DateSource::Exif);
     }
+    if let Some(dt) = file_mtime(path) {
+        return (Some(dt), DateSource::Mtime);
+    }
+    (None, DateSource::None)
+}
+
+pub fn best_datetime_for_video(
+    path: &Path,
+    probe: Option<&ProbeInfo>,
+) -> (Option<NaiveDateTime>, DateSource) {
+    if let Some(dt) = probe.and_then(|p| p.creation_time) {
+        return (Some(dt), DateSource::Ffprobe);
+    }
+    if let Some(dt) = file_mtime(path) {
+        return (Some(dt), DateSource::Mtime);
+    }
+    (None, DateSource::None)
 }
 
 pub fn best_datetime_for_dvd(dvd_root: &Path) -> (Option<NaiveDateTime>, DateSource) {
@@ -0,0 +1,165 @@
+use crate::apply::move_file;
+use crate::atomic;
+use crate::deduplicate::blake3_hash_file;
+use crate::journal::{ItemState, Journal, JournalEntry, ReleasedSource};
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub struct UndoSummary {
+    /// Items fully reversed and reset to pending in the journal.
+    pub undone: u64,
+    pub removed: u64,
+    pub sources_restored: u64,
+    pub dirs_pruned: u64,
+    /// Outputs left in place because they changed after apply wrote them.
+    pub kept_modified: Vec<String>,
+    /// Outputs left in place because they replaced a different file.
+    pub kept_replaced: Vec<String>,
+    pub failures: Vec<String>,
+}
+
+impl UndoSummary {
+    pub fn new() -> Self {
+        Self {
+            undone: 0,
+            removed: 0,
+            sources_restored: 0,
+            dirs_pruned: 0,
+            kept_modified: Vec::new(),
+            kept_replaced: Vec::new(),
+            failures: Vec::new(),
+        }
+    }
+}
+
+fn hash_hex(path: &Path) -> Option<String> {
+    blake3_hash_file(path).ok().map(|h| h.to_hex().to_string())
+}
+
+/// Puts a moved source back: from quarantine if it went there, otherwise by
+/// copying the output it was verified against.
+fn restore_source(src: &Path, released: &ReleasedSource) -> Result<()> {
+    if src.exists() {
+        anyhow::ensure!(
+            hash_hex(src).as_deref() == Some(released.hash.as_str()),
+            "{} exists with different content",
+            src.display()
+        );
+        return Ok(());
+    }
+
+    if let Some(quarantined) = &released.quarantined_to {
+        return move_file(Path::new(quarantined), src)
+            .with_context(|| format!("restore {} from {}", src.display(), quarantined));
+    }
+
+    let copy = Path::new(&released.verified_against);
+    anyhow::ensure!(
+        hash_hex(copy).as_deref() == Some(released.hash.as_str()),
+        "cannot restore {}: {} no longer matches it",
+        src.display(),
+        copy.display()
+    );
+    atomic::write_atomically(src, |tmp| {
+        fs::copy(copy, tmp).with_context(|| format!("restore {}", src.display()))?;
+        Ok(())
+    })
+}
+
+/// Reverses the items `journal` records as done, and whatever interrupted or
+/// failed items left behind, newest first. Sources are restored before any
+/// output is removed, since a moved source may only survive in the output
+/// it was verified against.
+pub fn undo(journal: &mut Journal) -> Result<UndoSummary> {
+    let mut summary = UndoSummary::new();
+    let done: Vec<JournalEntry> = journal
+        .entries_newest_first()
+        .into_iter()
+        .filter(|e| match e.state {
+            ItemState::Done => true,
+            ItemState::InProgress | ItemState::Failed => !e.effects.is_empty(),
+            ItemState::Pending => false,
+        })
+        .cloned()
+        .collect();
+
+    let mut unrestored: HashSet<&str> = HashSet::new();
+    for e in &done {
+        let Some(released) = &e.effects.released else {
+            continue;
+        };
+        match restore_source(Path::new(&e.src), released) {
+            Ok(()) => summary.sources_restored += 1,
+            Err(err) => {
+                summary.failures.push(format!("{err:#}"));
+                unrestored.insert(&e.src);
+            }
+        }
+    }
+
+    let mut created_dirs: Vec<&str> = Vec::new();
+    for e in &done {
+        // Without its source back, the output is the only copy left.
+        if unrestored.contains(e.src.as_str()) {
+            continue;
+        }
+        // A source that is gone with no release on record went somewhere
+        // undo cannot follow, so the outputs are all that is known of it.
+        if e.effects.released.is_none() && !Path::new(&e.src).exists() {
+            summary.failures.push(format!(
+                "{} is missing and was not released by apply; outputs kept",
+                e.src
+            ));
+            continue;
+        }
+
+        let mut complete = true;
+        for created in e.effects.created.iter().rev() {
+            let path = PathBuf::from(&created.path);
+            if !path.exists() && !path.is_symlink() {
+                continue;
+            }
+            // Removing it would not bring back the file it replaced.
+            if created.replaced {
+                summary.kept_replaced.push(created.path.clone());
+                complete = false;
+                continue;
+            }
+            if hash_hex(&path).as_deref() != Some(created.hash.as_str()) {
+                summary.kept_modified.push(created.path.clone());
+                complete = false;
+                continue;
+            }
+            match fs::remove_file(&path) {
+                Ok(()) => summary.removed += 1,
+                Err(err) => {
+                    summary
+                        .failures
+                        .push(format!("remove {}: {err}", path.display()));
+                    complete = false;
+                }
+            }
+        }
+
+        if complete {
+            journal.reset(e)?;
+            summary.undone += 1;
+        }
+        created_dirs.extend(e.effects.created_dirs.iter().map(String::as_str));
+    }
+
+    // Deepest first, so parents are empty by the time they are tried.
+    // Directories still holding anything are left alone.
+    created_dirs.sort();
+    created_dirs.dedup();
+    created_dirs.sort_by_key(|d| std::cmp::Reverse(Path::new(d).components().count()));
+    for dir in created_dirs {
+        if fs::remove_dir(dir).is_ok() {
+            summary.dirs_pruned += 1;
+        }
+    }
+
+    Ok(summary)
+}
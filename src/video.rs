@@ -1,13 +1,93 @@
 use anyhow::{Context, Result};
 use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
-use std::{path::Path, process::Command};
+use std::io::{BufRead, BufReader};
+use std::{
+    path::Path,
+    process::{Command, Stdio},
+};
 
-use crate::apply::ensure_parent_dir;
+use crate::atomic;
+use crate::cancel;
 
-pub fn ffprobe_creation_time(path: &Path) -> Result<Option<NaiveDateTime>> {
+/// Codec settings passed to ffmpeg when re-encoding.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EncodingProfile {
+    pub video_codec: String,
+    pub audio_codec: String,
+    pub crf: Option<u8>,
+    pub preset: Option<String>,
+    /// Passed to ffmpeg verbatim after the codec options.
+    pub extra_args: Vec<String>,
+}
+
+impl Default for EncodingProfile {
+    fn default() -> Self {
+        Self {
+            video_codec: "libx264".into(),
+            audio_codec: "aac".into(),
+            crf: None,
+            preset: None,
+            extra_args: Vec::new(),
+        }
+    }
+}
+
+impl EncodingProfile {
+    pub fn codec_args(&self) -> Vec<String> {
+        let mut args = vec![
+            "-c:v".to_string(),
+            self.video_codec.clone(),
+            "-c:a".to_string(),
+            self.audio_codec.clone(),
+        ];
+        if let Some(crf) = self.crf {
+            args.extend(["-crf".to_string(), crf.to_string()]);
+        }
+        if let Some(preset) = &self.preset {
+            args.extend(["-preset".to_string(), preset.clone()]);
+        }
+        args.extend(self.extra_args.iter().cloned());
+        args
+    }
+}
+
+/// Container and stream details read with a single ffprobe call.
+#[derive(Debug, Clone, Default)]
+pub struct ProbeInfo {
+    pub creation_time: Option<NaiveDateTime>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Muxer that wrote the file, e.g. `Lavf58.29.100` for ffmpeg.
+    pub encoder: Option<String>,
+    pub duration_secs: Option<f64>,
+}
+
+impl ProbeInfo {
+    /// Files written by ffmpeg or HandBrake are re-encodes, not camera originals.
+    pub fn is_reencode(&self) -> bool {
+        self.encoder
+            .as_deref()
+            .is_some_and(|e| e.starts_with("Lavf") || e.contains("HandBrake"))
+    }
+}
+
+fn json_u64(v: Option<&JsonValue>) -> Option<u64> {
+    v.and_then(|v| v.as_u64().or_else(|| v.as_str()?.parse().ok()))
+}
+
+pub fn ffprobe(path: &Path) -> Result<Option<ProbeInfo>> {
     let output = Command::new("ffprobe")
-        .args(["-v", "quiet", "-print_format", "json", "-show_format"])
+        .args([
+            "-v",
+            "quiet",
+            "-print_format",
+            "json",
+            "-show_format",
+            "-show_streams",
+        ])
         .arg(path)
         .output()?;
 
@@ -16,46 +96,105 @@ pub fn ffprobe_creation_time(path: &Path) -> Result<Option<NaiveDateTime>> {
     }
 
     let json: JsonValue = serde_json::from_slice(&output.stdout)?;
-    let creation = json
-        .get("format")
-        .and_then(|f| f.get("tags"))
-        .and_then(|t| t.get("creation_time"))
-        .and_then(|v| v.as_str());
+    let format = json.get("format");
+    let tags = format.and_then(|f| f.get("tags"));
 
-    let dt = creation
+    let creation_time = tags
+        .and_then(|t| t.get("creation_time"))
+        .and_then(|v| v.as_str())
         .and_then(|s| chrono::DateTime::parse_from_rfc3339(s).ok())
         .map(|dt| dt.naive_local());
 
-    Ok(dt)
+    let video_stream = json
+        .get("streams")
+        .and_then(|s| s.as_array())
+        .and_then(|streams| {
+            streams
+                .iter()
+                .find(|s| s.get("codec_type").and_then(|t| t.as_str()) == Some("video"))
+        });
+
+    Ok(Some(ProbeInfo {
+        creation_time,
+        width: json_u64(video_stream.and_then(|s| s.get("width"))).map(|w| w as u32),
+        height: json_u64(video_stream.and_then(|s| s.get("height"))).map(|h| h as u32),
+        encoder: tags
+            .and_then(|t| t.get("encoder"))
+            .and_then(|v| v.as_str())
+            .map(String::from),
+        duration_secs: format
+            .and_then(|f| f.get("duration"))
+            .and_then(|v| v.as_str())
+            .and_then(|s| s.parse().ok()),
+    }))
 }
 
-pub fn ffmpeg_convert_to_mp4(src: &Path, dst: &Path) -> Result<()> {
-    ensure_parent_dir(dst)?;
+/// Decodes the frame nearest `at_secs` and returns it scaled to `width` x
+/// `height` greyscale pixels, row-major. `None` if ffmpeg cannot produce it.
+pub fn ffmpeg_grey_frame(path: &Path, at_secs: f64, width: u32, height: u32) -> Option<Vec<u8>> {
+    let output = Command::new("ffmpeg")
+        .args(["-v", "quiet", "-ss", &format!("{at_secs:.3}"), "-i"])
+        .arg(path)
+        .args(["-frames:v", "1", "-an", "-sn"])
+        .args(["-vf", &format!("scale={width}:{height},format=gray")])
+        .args(["-f", "rawvideo", "-"])
+        .output()
+        .ok()?;
 
-    let status = Command::new("ffmpeg")
-        .args([
-            "-y",
-            "-hide_banner",
-            "-loglevel",
-            "error",
-            "-i",
-            src.to_str().unwrap(),
-            "-c:v",
-            "libx264",
-            "-c:a",
-            "aac",
-            "-movflags",
-            "+faststart",
-            dst.to_str().unwrap(),
-        ])
-        .status()
-        .with_context(|| "failed to spawn ffmpeg")?;
+    let expected = (width * height) as usize;
+    (output.status.success() && output.stdout.len() == expected).then_some(output.stdout)
+}
+
+/// The ffmpeg invocation that converts `src` into `out`.
+pub fn ffmpeg_convert_command(src: &Path, out: &Path, profile: &EncodingProfile) -> Command {
+    let mut cmd = Command::new("ffmpeg");
+    cmd.args(["-y", "-hide_banner", "-loglevel", "error", "-nostats"])
+        .args(["-progress", "pipe:1", "-i"])
+        .arg(src)
+        .args(profile.codec_args())
+        .args(["-movflags", "+faststart"])
+        .arg(out);
+    cmd
+}
+
+/// Converts `src` to mp4, calling `on_progress` with the seconds of output
+/// written so far as ffmpeg reports them.
+pub fn ffmpeg_convert_to_mp4(
+    src: &Path,
+    dst: &Path,
+    profile: &EncodingProfile,
+    on_progress: &dyn Fn(f64),
+) -> Result<()> {
+    atomic::write_atomically(dst, |tmp| {
+        let mut child = ffmpeg_convert_command(src, tmp, profile)
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| "failed to spawn ffmpeg")?;
 
-    anyhow::ensure!(
-        status.success(),
-        "ffmpeg failed converting {}",
-        src.display()
-    );
+        if let Some(stdout) = child.stdout.take() {
+            for line in BufReader::new(stdout).lines().map_while(|l| l.ok()) {
+                // `out_time_ms` is in microseconds too, despite its name.
+                let micros = line
+                    .strip_prefix("out_time_us=")
+                    .or_else(|| line.strip_prefix("out_time_ms="))
+                    .and_then(|v| v.trim().parse::<u64>().ok());
+                if let Some(us) = micros {
+                    on_progress(us as f64 / 1_000_000.0);
+                }
+                if cancel::requested() {
+                    let _ = child.kill();
+                    break;
+                }
+            }
+        }
+        let status = child.wait().with_context(|| "failed to wait for ffmpeg")?;
+        cancel::check()?;
 
-    Ok(())
+        anyhow::ensure!(
+            status.success(),
+            "ffmpeg failed converting {}",
+            src.display()
+        );
+        Ok(())
+    })
 }
@@ -0,0 +1,78 @@
+use anyhow::Result;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+
+/// Caps how many holders run at once, e.g. external ffprobe/ffmpeg processes,
+/// independently of how many worker threads exist.
+pub struct Semaphore {
+    available: Mutex<usize>,
+    freed: Condvar,
+}
+
+pub struct Permit<'a> {
+    sem: &'a Semaphore,
+}
+
+impl Semaphore {
+    pub fn new(permits: usize) -> Self {
+        Self {
+            available: Mutex::new(permits.max(1)),
+            freed: Condvar::new(),
+        }
+    }
+
+    pub fn acquire(&self) -> Permit<'_> {
+        let mut n = self.available.lock().unwrap();
+        while *n == 0 {
+            n = self.freed.wait(n).unwrap();
+        }
+        *n -= 1;
+        Permit { sem: self }
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        *self.sem.available.lock().unwrap() += 1;
+        self.sem.freed.notify_one();
+    }
+}
+
+/// A rayon pool sized by config; 0 means one thread per CPU.
+pub fn thread_pool(threads: usize) -> Result<rayon::ThreadPool> {
+    Ok(rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()?)
+}
+
+/// Runs `f` over `indices` on `jobs` scoped threads, each taking the next
+/// unclaimed index, so a slow item only holds up its own thread. Stops
+/// handing out work after the first error, which is returned.
+pub fn for_each_queued(
+    indices: &[usize],
+    jobs: usize,
+    f: impl Fn(usize) -> Result<()> + Sync,
+) -> Result<()> {
+    let next = AtomicUsize::new(0);
+    let error: Mutex<Option<anyhow::Error>> = Mutex::new(None);
+
+    std::thread::scope(|s| {
+        for _ in 0..jobs.max(1).min(indices.len()) {
+            s.spawn(|| {
+                while error.lock().unwrap().is_none() {
+                    let Some(&i) = indices.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                        break;
+                    };
+                    if let Err(e) = f(i) {
+                        error.lock().unwrap().get_or_insert(e);
+                    }
+                }
+            });
+        }
+    });
+
+    match error.into_inner().unwrap() {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
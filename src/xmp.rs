@@ -0,0 +1,39 @@
+use crate::atomic;
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+pub fn sidecar_path(dst: &Path) -> PathBuf {
+    dst.with_extension("xmp")
+}
+
+/// Writes a minimal XMP sidecar next to `dst` carrying the source filename in
+/// `xmpMM:PreservedFileName`, which Lightroom, darktable and exiftool all read.
+pub fn write_preserved_filename_sidecar(dst: &Path, original_name: &str) -> Result<()> {
+    let path = sidecar_path(dst);
+
+    let xml = format!(
+        r#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:xmpMM="http://ns.adobe.com/xap/1.0/mm/"
+    xmpMM:PreservedFileName="{}"/>
+ </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end="w"?>
+"#,
+        escape_xml(original_name)
+    );
+
+    atomic::write_atomically(&path, |tmp| {
+        std::fs::write(tmp, &xml).with_context(|| format!("write xmp {}", path.display()))
+    })
+}